@@ -0,0 +1,234 @@
+use std::ops::Range;
+
+/// Text formatting selected by SGR escapes.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Format {
+    pub fg: Option<egui::Color32>,
+    pub bg: Option<egui::Color32>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub inverse: bool,
+}
+
+/// xterm's default 16 colour palette.
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+fn colour_256(n: u8) -> egui::Color32 {
+    match n {
+        0..=15 => {
+            let (r, g, b) = PALETTE[n as usize];
+            egui::Color32::from_rgb(r, g, b)
+        },
+        16..=231 => {
+            let n = n - 16;
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            egui::Color32::from_rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+        },
+        232..=255 => {
+            let v = 8 + (n - 232) * 10;
+            egui::Color32::from_rgb(v, v, v)
+        },
+    }
+}
+
+impl Format {
+    /// Apply the parameters of a `CSI ... m` sequence.
+    fn apply_sgr(&mut self, params: &[u16]) {
+        let mut params = params.iter().copied();
+
+        // an extended colour, `5;n` or `2;r;g;b`, `None` if it's out of range
+        fn extended(params: &mut impl Iterator<Item = u16>) -> Option<egui::Color32> {
+            match params.next()? {
+                5 => u8::try_from(params.next()?).ok().map(colour_256),
+                2 => {
+                    // all three are taken even if one is out of range, so none are read as codes of their own
+                    let (r, g, b) = (params.next()?, params.next()?, params.next()?);
+                    Some(egui::Color32::from_rgb(u8::try_from(r).ok()?, u8::try_from(g).ok()?, u8::try_from(b).ok()?))
+                },
+                _ => None,
+            }
+        }
+
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Format::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                7 => self.inverse = true,
+                9 => self.strikethrough = true,
+                21 | 22 => {
+                    self.bold = false;
+                    self.dim = false;
+                },
+                23 => self.italic = false,
+                24 => self.underline = false,
+                27 => self.inverse = false,
+                29 => self.strikethrough = false,
+                30..=37 => self.fg = Some(colour_256((param - 30) as u8)),
+                38 => self.fg = extended(&mut params).or(self.fg),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(colour_256((param - 40) as u8)),
+                48 => self.bg = extended(&mut params).or(self.bg),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(colour_256((param - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(colour_256((param - 100 + 8) as u8)),
+                _ => {},
+            }
+        }
+    }
+
+    fn text_format(&self, font_id: &egui::FontId, visuals: &egui::Visuals) -> egui::TextFormat {
+        let mut fg = match self.fg {
+            // bold text uses the bright variant of the basic colours, like most terminals
+            Some(fg) if self.bold => PALETTE[..8].iter()
+                .position(|&(r, g, b)| egui::Color32::from_rgb(r, g, b) == fg)
+                .map(|i| colour_256(i as u8 + 8))
+                .unwrap_or(fg),
+            Some(fg) => fg,
+            None if self.bold => visuals.strong_text_color(),
+            None => visuals.text_color(),
+        };
+        let mut bg = self.bg.unwrap_or(egui::Color32::TRANSPARENT);
+
+        if self.inverse {
+            (fg, bg) = (if bg == egui::Color32::TRANSPARENT { visuals.extreme_bg_color } else { bg }, fg);
+        }
+        if self.dim {
+            fg = fg.gamma_multiply(0.6);
+        }
+
+        egui::TextFormat {
+            font_id: font_id.clone(),
+            color: fg,
+            background: bg,
+            italics: self.italic,
+            underline: if self.underline { egui::Stroke::new(1.0, fg) } else { egui::Stroke::NONE },
+            strikethrough: if self.strikethrough { egui::Stroke::new(1.0, fg) } else { egui::Stroke::NONE },
+            ..Default::default()
+        }
+    }
+}
+
+/// Whether `text` contains anything [`parse`] would remove.
+pub fn has_escapes(text: &str) -> bool {
+    text.contains(['\x1b', '\u{9b}'])
+}
+
+/// Remove all escape sequences from `raw`, returning the remaining text and
+/// the SGR formatting of each run of it.
+///
+/// Only SGR (`CSI ... m`) sequences affect the output, all other control
+/// sequences are dropped.
+pub fn parse(raw: &str) -> (String, Vec<(Range<usize>, Format)>) {
+    let mut text = String::with_capacity(raw.len());
+    let mut spans: Vec<(Range<usize>, Format)> = Vec::new();
+    let mut format = Format::default();
+
+    let mut chars = raw.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{9b}' => {},
+            '\x1b' => match chars.next() {
+                Some('[') => {},
+                // string sequences, terminated by BEL or ST (ESC \)
+                Some(']' | 'P' | 'X' | '^' | '_') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || c == '\u{9c}' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                    }
+                    continue;
+                },
+                // character set designation takes one more character
+                Some('(' | ')' | '*' | '+' | '-' | '.' | '/') => {
+                    chars.next();
+                    continue;
+                },
+                _ => continue,
+            },
+            c => {
+                let start = text.len();
+                text.push(c);
+                match spans.last_mut() {
+                    Some((range, f)) if *f == format && range.end == start => range.end = text.len(),
+                    _ => spans.push((start..text.len(), format)),
+                }
+                continue;
+            },
+        }
+
+        // control sequence, parameters up to a final byte
+        let mut params = String::new();
+        let mut terminator = None;
+        for c in chars.by_ref() {
+            if ('\x40'..='\x7e').contains(&c) {
+                terminator = Some(c);
+                break;
+            }
+            params.push(c);
+        }
+
+        // private sequences (e.g. `CSI ? 25 l`) are never SGR
+        if terminator == Some('m') && params.chars().all(|c| c.is_ascii_digit() || c == ';' || c == ':') {
+            let params: Vec<u16> = params.split([';', ':'])
+                // an empty parameter is 0, one too big to parse is out of range of everything
+                .map(|p| if p.is_empty() { 0 } else { p.parse().unwrap_or(u16::MAX) })
+                .collect();
+            format.apply_sgr(&params);
+        }
+    }
+
+    (text, spans)
+}
+
+/// Lay out text produced by [`parse`] with its formatting.
+pub fn layout(text: &str, spans: &[(Range<usize>, Format)], font_id: &egui::FontId, visuals: &egui::Visuals) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+
+    // every character belongs to a span, so no gaps to fill in
+    for (range, format) in spans {
+        job.append(&text[range.clone()], 0.0, format.text_format(font_id, visuals));
+    }
+
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_become_formatting() {
+        let raw = "\x1b[31mred\x1b[0m \x1b[1;4mstrong\x1b[m \x1b[38;5;196mhigh\x1b[38;5;300m still\x1b[0m \
+            \x1b[48;2;1;2;3mtrue\x1b[49m\x1b[2J\x1b[?25l\x1b]0;title\x07 plain";
+        let (text, spans) = parse(raw);
+        assert_eq!(text, "red strong high still true plain");
+
+        let format_of = |word: &str| {
+            let start = text.find(word).unwrap();
+            spans.iter().find(|(range, _)| range.contains(&start)).unwrap().1
+        };
+        assert_eq!(format_of("red").fg, Some(egui::Color32::from_rgb(205, 0, 0)));
+        assert_eq!(format_of("strong"), Format { bold: true, underline: true, ..Default::default() });
+        assert_eq!(format_of("high").fg, Some(egui::Color32::from_rgb(255, 0, 0)));
+        // 300 isn't a colour, so the one before it stays
+        assert_eq!(format_of("still").fg, Some(egui::Color32::from_rgb(255, 0, 0)));
+        assert_eq!(format_of("true").bg, Some(egui::Color32::from_rgb(1, 2, 3)));
+        assert_eq!(format_of("plain"), Format::default());
+    }
+}
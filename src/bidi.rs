@@ -0,0 +1,186 @@
+//! Right-to-left and mixed direction text, reordered with the Unicode bidirectional algorithm.
+//!
+//! egui only lays text out left to right, so lines are wrapped in logical order
+//! first and then each row is reordered for display.
+
+use unic::bidi::{BidiClass, BidiInfo, Level};
+
+/// Languages written right to left, by primary language subtag.
+const RTL_LANGUAGES: [&str; 15] = ["ar", "arc", "ckb", "dv", "fa", "he", "iw", "ji", "nqo", "ps", "sd", "syr", "ug", "ur", "yi"];
+
+/// Whether `lang`, the `lang` parameter of a `text/gemini` response, is written right to left.
+///
+/// Only the first of a comma separated list is considered, e.g. `fa-IR,en`.
+pub fn is_rtl_language(lang: &str) -> bool {
+    let primary = lang.split([',', '-', '_']).next().unwrap_or_default().trim().to_ascii_lowercase();
+    RTL_LANGUAGES.contains(&primary.as_str())
+}
+
+/// Direction of a paragraph from its first strong character, or `default_rtl` if it has none.
+pub fn is_rtl(text: &str, default_rtl: bool) -> bool {
+    text.chars()
+        .map(BidiClass::of)
+        .find(|class| matches!(class, BidiClass::LeftToRight | BidiClass::RightToLeft | BidiClass::ArabicLetter))
+        .map_or(default_rtl, |class| class != BidiClass::LeftToRight)
+}
+
+/// Whether a paragraph can't just be laid out left to right as it is.
+pub fn needs_reordering(text: &str, rtl: bool) -> bool {
+    rtl || text.chars().any(|c| BidiClass::of(c).is_rtl())
+}
+
+/// Lay out `job` wrapped at `width`, then reorder each row for display, with rows separated by newlines.
+///
+/// Also returns, for each char of the new text, its byte offset into `job.text`
+/// and whether it reads right to left, followed by the length of `job.text`.
+pub fn reorder(ui: &egui::Ui, job: egui::text::LayoutJob, width: f32, rtl: bool) -> (egui::text::LayoutJob, Vec<(usize, bool)>) {
+    let mut wrapped = job.clone();
+    wrapped.wrap.max_width = width;
+    let galley = ui.fonts(|f| f.layout_job(wrapped));
+
+    let level = if rtl { Level::rtl() } else { Level::ltr() };
+    let info = BidiInfo::new(&job.text, Some(level));
+    let chars: Vec<(usize, char)> = job.text.char_indices().collect();
+
+    let mut out = egui::text::LayoutJob::default();
+    let mut order = Vec::with_capacity(chars.len() + galley.rows.len());
+
+    let mut start = 0;
+    for (r, row) in galley.rows.iter().enumerate() {
+        let end = (start + row.glyphs.len()).min(chars.len());
+        let row_chars = &chars[start..end];
+
+        let classes: Vec<BidiClass> = row_chars.iter().map(|(b, _)| info.original_classes[*b]).collect();
+        let levels: Vec<Level> = row_chars.iter().map(|(b, _)| info.levels[*b]).collect();
+
+        let mut run = String::new();
+        let mut run_section: Option<usize> = None;
+        for i in visual_order(&classes, levels, level) {
+            let (byte, c) = row_chars[i];
+            let section = job.sections.partition_point(|s| s.byte_range.end <= byte).min(job.sections.len() - 1);
+            if let Some(previous) = run_section.filter(|s| *s != section) {
+                out.append(&std::mem::take(&mut run), 0.0, job.sections[previous].format.clone());
+            }
+            run_section = Some(section);
+            let char_rtl = info.levels[byte].is_rtl();
+            run.push(if char_rtl { mirror(c) } else { c });
+            order.push((byte, char_rtl));
+        }
+
+        if r + 1 < galley.rows.len() {
+            run.push('\n');
+            order.push((chars.get(end).map_or(job.text.len(), |(b, _)| *b), rtl));
+        }
+        if let Some(section) = run_section.or(job.sections.len().checked_sub(1)) {
+            out.append(&run, 0.0, job.sections[section].format.clone());
+        }
+
+        start = end + row.ends_with_newline as usize;
+    }
+    order.push((job.text.len(), rtl));
+
+    (out, order)
+}
+
+/// Indices of a row's chars in the order they're shown, given their classes and resolved levels.
+///
+/// <https://www.unicode.org/reports/tr9/#Reordering_Resolved_Levels>
+fn visual_order(classes: &[BidiClass], mut levels: Vec<Level>, paragraph: Level) -> Vec<usize> {
+    // L1: whitespace at the end of the row and before tabs goes back to the paragraph's direction
+    let mut trailing = true;
+    for i in (0..levels.len()).rev() {
+        match classes[i] {
+            BidiClass::SegmentSeparator | BidiClass::ParagraphSeparator => {
+                levels[i] = paragraph;
+                trailing = true;
+            },
+            BidiClass::WhiteSpace | BidiClass::BoundaryNeutral | BidiClass::FirstStrongIsolate
+                | BidiClass::LeftToRightIsolate | BidiClass::RightToLeftIsolate | BidiClass::PopDirectionalIsolate if trailing => {
+                levels[i] = paragraph;
+            },
+            _ => trailing = false,
+        }
+    }
+
+    // L2: reverse every run at or above each odd level, from the highest down
+    let mut order: Vec<usize> = (0..levels.len()).collect();
+    let highest = levels.iter().map(Level::number).max().unwrap_or(0);
+    let lowest_odd = levels.iter().map(Level::number).filter(|n| n % 2 == 1).min().unwrap_or(highest + 1);
+
+    for depth in (lowest_odd..=highest).rev() {
+        let mut i = 0;
+        while i < order.len() {
+            if levels[order[i]].number() < depth {
+                i += 1;
+                continue;
+            }
+            let end = (i..order.len()).find(|j| levels[order[*j]].number() < depth).unwrap_or(order.len());
+            order[i..end].reverse();
+            i = end;
+        }
+    }
+
+    order
+}
+
+/// Brackets and the like face the other way in right to left text.
+fn mirror(c: char) -> char {
+    match c {
+        '(' => ')',
+        ')' => '(',
+        '[' => ']',
+        ']' => '[',
+        '{' => '}',
+        '}' => '{',
+        '<' => '>',
+        '>' => '<',
+        '«' => '»',
+        '»' => '«',
+        '‹' => '›',
+        '›' => '‹',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(text: &str, rtl: bool) -> String {
+        let level = if rtl { Level::rtl() } else { Level::ltr() };
+        let info = BidiInfo::new(text, Some(level));
+        let chars: Vec<(usize, char)> = text.char_indices().collect();
+        let classes = chars.iter().map(|(b, _)| info.original_classes[*b]).collect::<Vec<_>>();
+        let levels = chars.iter().map(|(b, _)| info.levels[*b]).collect();
+
+        visual_order(&classes, levels, level).into_iter()
+            .map(|i| if info.levels[chars[i].0].is_rtl() { mirror(chars[i].1) } else { chars[i].1 })
+            .collect()
+    }
+
+    #[test]
+    fn mixed_direction_lines_are_reordered() {
+        assert!(is_rtl_language("fa"));
+        assert!(is_rtl_language("he-IL,en"));
+        assert!(!is_rtl_language("en-GB"));
+
+        assert!(is_rtl("«שלום» world", false));
+        assert!(!is_rtl("hello עולם", true));
+        assert!(is_rtl("123 ...", true));
+
+        // english inside hebrew keeps its own order, and the full stop ends up on the left
+        assert_eq!(display("שלום world.", true), ".world םולש");
+        // numbers read left to right even in arabic
+        assert_eq!(display("عام 2024", true), "2024 ماع");
+        // hebrew inside english is reversed in place
+        assert_eq!(display("say שלום (hi)", false), "say םולש (hi)");
+        assert_eq!(display("(שלום)", true), "(םולש)");
+
+        for page in [include_str!("../test-pages/mixed-direction.gmi"), include_str!("../test-pages/arabic.gmi")] {
+            for line in page.lines() {
+                let rtl = is_rtl(line, true);
+                assert_eq!(display(line, rtl).chars().count(), line.chars().count());
+            }
+        }
+    }
+}
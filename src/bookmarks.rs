@@ -0,0 +1,64 @@
+use std::sync::{Mutex, MutexGuard};
+
+pub const STORAGE_KEY: &str = "bookmarks";
+
+pub const PAGE_URL: &str = "about://bookmarks";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Bookmark {
+    pub url: String,
+    pub title: String,
+}
+
+static BOOKMARKS: Mutex<Vec<Bookmark>> = Mutex::new(Vec::new());
+
+fn bookmarks() -> MutexGuard<'static, Vec<Bookmark>> {
+    BOOKMARKS.lock().expect("bookmarks lock poisoned")
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) {
+    if let Some(loaded) = storage.and_then(|storage| eframe::get_value(storage, STORAGE_KEY)) {
+        *bookmarks() = loaded;
+    }
+}
+
+pub fn save(storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, STORAGE_KEY, &*bookmarks());
+}
+
+pub fn is_bookmarked(url: &str) -> bool {
+    bookmarks().iter().any(|b| b.url == url)
+}
+
+/// Bookmark the absolute `url`, unless it already is.
+pub fn add(url: &str, title: &str) {
+    let mut bookmarks = bookmarks();
+    if bookmarks.iter().any(|b| b.url == url) {
+        return;
+    }
+
+    bookmarks.push(Bookmark {
+        url: url.into(),
+        title: if title.trim().is_empty() { url } else { title.trim() }.into(),
+    });
+}
+
+pub fn remove(url: &str) {
+    bookmarks().retain(|b| b.url != url);
+}
+
+/// The `about://bookmarks` page, oldest first.
+pub fn page() -> String {
+    let bookmarks = bookmarks();
+
+    let mut page = String::from("# Bookmarks\n\n");
+
+    if bookmarks.is_empty() {
+        page += "Nothing bookmarked yet. Use the bookmark button next to the address bar, or right click a link.\n";
+    }
+    for bookmark in bookmarks.iter() {
+        page += &format!("=> {} {}\n", bookmark.url, bookmark.title);
+    }
+
+    page
+}
@@ -0,0 +1,380 @@
+//! Files saved to disk as they arrive, listed in `about://downloads`.
+
+use std::{fs, io::{self, Read, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicU64, Ordering}, Mutex, MutexGuard}, thread, time::Duration};
+
+use crate::{gemini, handlers, viewer, xmlfeed};
+
+pub const STORAGE_KEY: &str = "downloads";
+
+pub const PAGE_URL: &str = "about://downloads";
+
+/// How long a download waits for the server to send more before it fails.
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum State {
+    Active,
+    Finished,
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Download {
+    /// Used in the links on the downloads page.
+    pub id: u64,
+    pub url: String,
+    pub path: PathBuf,
+    /// Bytes written so far, the size isn't known until the server closes the connection.
+    pub received: u64,
+    pub state: State,
+    /// Counts retries, so a thread still finishing an earlier attempt can tell it's been replaced.
+    #[serde(default)]
+    attempt: u64,
+}
+
+static DOWNLOADS: Mutex<Vec<Download>> = Mutex::new(Vec::new());
+
+/// Id of the next download, never reused so links on an old downloads page can't reach a different one.
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Held while a download's file is created or written, which is kept out of the list's lock the downloads page takes every frame.
+static WRITING: Mutex<()> = Mutex::new(());
+
+/// Bumped whenever the list changes, so the downloads page can be shown again when it does.
+static VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// Folder set in the settings, empty for the system's downloads folder.
+static DIRECTORY: Mutex<String> = Mutex::new(String::new());
+
+fn downloads() -> MutexGuard<'static, Vec<Download>> {
+    DOWNLOADS.lock().expect("downloads lock poisoned")
+}
+
+fn writing() -> MutexGuard<'static, ()> {
+    WRITING.lock().expect("download writing lock poisoned")
+}
+
+/// The download with `id`, if `attempt` is still the one it's on.
+fn current(downloads: &mut [Download], id: u64, attempt: u64) -> Result<&mut Download, String> {
+    let download = downloads.iter_mut().find(|d| d.id == id).ok_or("removed from the list")?;
+    if download.attempt != attempt || download.state != State::Active {
+        return Err("cancelled".into());
+    }
+    Ok(download)
+}
+
+fn changed() {
+    VERSION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Changes whenever the list does, so the downloads page can be made again, see [`page`].
+pub fn version() -> u64 {
+    VERSION.load(Ordering::Acquire)
+}
+
+pub fn any_active() -> bool {
+    downloads().iter().any(|d| d.state == State::Active)
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) {
+    if let Some(mut loaded) = storage.and_then(|storage| eframe::get_value::<Vec<Download>>(storage, STORAGE_KEY)) {
+        for download in &mut loaded {
+            if download.state == State::Active {
+                download.state = State::Failed("Vostok was closed".into());
+            }
+        }
+        let next = loaded.iter().map(|d| d.id + 1).max().unwrap_or_default();
+        NEXT_ID.fetch_max(next, Ordering::AcqRel);
+        *downloads() = loaded;
+        changed();
+    }
+}
+
+pub fn save(storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, STORAGE_KEY, &*downloads());
+}
+
+pub fn set_directory(directory: &str) {
+    directory.clone_into(&mut DIRECTORY.lock().expect("download directory lock poisoned"));
+}
+
+/// Where files are saved.
+pub fn directory() -> Option<PathBuf> {
+    let configured = DIRECTORY.lock().expect("download directory lock poisoned").trim().to_owned();
+    if configured.is_empty() {
+        default_directory()
+    } else {
+        Some(configured.into())
+    }
+}
+
+pub fn default_directory() -> Option<PathBuf> {
+    dirs::download_dir().or_else(dirs::home_dir)
+}
+
+/// Name to save `url` as, from the last part of its path.
+pub fn file_name(url: &url::Url) -> String {
+    let name = url.path_segments().and_then(|mut s| s.next_back()).unwrap_or_default();
+    let name = url::form_urlencoded::parse(format!("n={}", name.replace('+', "%2B")).as_bytes())
+        .next()
+        .map(|(_, value)| value.replace(['/', '\\'], "_"))
+        .unwrap_or_default();
+    if name.trim_matches('.').is_empty() { "download".into() } else { name }
+}
+
+/// `name` in `dir`, numbered so it doesn't replace anything already there.
+pub fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+
+    let mut path = dir.join(name);
+    for n in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = dir.join(format!("{stem} ({n}){extension}"));
+    }
+    path
+}
+
+/// Create a new file named `name` in `dir`, see [`unique_path`], which nothing else can have made in the meantime.
+pub fn create_unique(dir: &Path, name: &str) -> io::Result<(PathBuf, fs::File)> {
+    loop {
+        let path = unique_path(dir, name);
+        match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Whether a response of `mimetype` can be shown in a tab rather than downloaded.
+pub fn can_show(mimetype: &str) -> bool {
+    let mimetype = mimetype.split(';').next().unwrap_or_default().trim();
+    mimetype.is_empty() || mimetype.starts_with("text/") || xmlfeed::is_feed(mimetype) || viewer::can_show(mimetype)
+}
+
+/// Add a download to the list, creating its file so nothing else takes the name.
+fn add(url: &url::Url, name: &str) -> Result<(u64, PathBuf), String> {
+    let dir = directory().ok_or("there's no downloads folder")?;
+    fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+
+    let mut downloads = downloads();
+    let (path, _) = create_unique(&dir, name).map_err(|err| err.to_string())?;
+
+    let id = NEXT_ID.fetch_add(1, Ordering::AcqRel);
+    downloads.push(Download {
+        id,
+        url: url.to_string(),
+        path: path.clone(),
+        received: 0,
+        state: State::Active,
+        attempt: 0,
+    });
+    changed();
+    Ok((id, path))
+}
+
+/// Download `url` as `name` in the downloads folder.
+pub fn start(url: url::Url, name: &str) -> Result<(), String> {
+    let (id, path) = add(&url, name)?;
+    thread::spawn(move || run(id, 0, &url, &path));
+    Ok(())
+}
+
+/// Save the rest of a response that's already been opened, named after `url`.
+pub fn start_stream(url: &url::Url, stream: gemini::Stream) -> Result<(), String> {
+    let (id, path) = add(url, &file_name(url))?;
+    thread::spawn(move || finish(id, 0, receive(id, 0, stream, &path)));
+    Ok(())
+}
+
+fn run(id: u64, attempt: u64, url: &url::Url, path: &Path) {
+    let result = gemini::open_following(url).and_then(|(_, stream)| receive(id, attempt, stream, path));
+    finish(id, attempt, result);
+}
+
+/// Write the body of `stream` to `path` as it arrives, until the download is cancelled or retried.
+fn receive(id: u64, attempt: u64, mut stream: gemini::Stream, path: &Path) -> Result<(), String> {
+    stream.set_read_timeout(Some(READ_TIMEOUT)).map_err(|err| err.to_string())?;
+    let mut file = {
+        let _writing = writing();
+        current(&mut downloads(), id, attempt)?;
+        fs::File::create(path).map_err(|err| err.to_string())?
+    };
+    let mut buffer = vec![0; 64 * 1024];
+
+    loop {
+        let read = match stream.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Err("the server stopped sending".into()),
+            Err(err) => return Err(err.to_string()),
+        };
+
+        // a retry can't create the file again until this attempt has stopped writing it
+        {
+            let _writing = writing();
+            current(&mut downloads(), id, attempt)?;
+            file.write_all(&buffer[..read]).map_err(|err| err.to_string())?;
+        }
+
+        current(&mut downloads(), id, attempt)?.received += read as u64;
+        changed();
+    }
+}
+
+fn finish(id: u64, attempt: u64, result: Result<(), String>) {
+    let mut downloads = downloads();
+    let Some(download) = downloads.iter_mut().find(|d| d.id == id && d.attempt == attempt) else {
+        return;
+    };
+
+    match (&download.state, result) {
+        (State::Cancelled, _) => {
+            // a partial file is no use to anyone
+            let _ = fs::remove_file(&download.path);
+        },
+        (_, Ok(())) => download.state = State::Finished,
+        (_, Err(err)) => download.state = State::Failed(err),
+    }
+    changed();
+}
+
+pub fn cancel(id: u64) {
+    if let Some(download) = downloads().iter_mut().find(|d| d.id == id && d.state == State::Active) {
+        download.state = State::Cancelled;
+        changed();
+    }
+}
+
+/// Download a failed or cancelled file again, to the same place.
+pub fn retry(id: u64) {
+    let mut downloads = downloads();
+    let Some(download) = downloads.iter_mut().find(|d| d.id == id && matches!(d.state, State::Failed(_) | State::Cancelled)) else {
+        return;
+    };
+    let Ok(url) = url::Url::parse(&download.url) else {
+        return;
+    };
+
+    download.state = State::Active;
+    download.received = 0;
+    download.attempt += 1;
+    let attempt = download.attempt;
+    let path = download.path.clone();
+    thread::spawn(move || run(id, attempt, &url, &path));
+    changed();
+}
+
+/// Save the rest of a response to a temporary file for another program to open, waiting until it's all arrived.
+pub fn save_temporary(url: &url::Url, mut stream: gemini::Stream) -> Result<PathBuf, String> {
+    let dir = std::env::temp_dir().join("vostok");
+    fs::create_dir_all(&dir).map_err(|err| err.to_string())?;
+
+    let (path, mut file) = create_unique(&dir, &file_name(url)).map_err(|err| err.to_string())?;
+    io::copy(&mut stream, &mut file).map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+/// Handle a link on the downloads page, like `about://downloads/cancel?3`.
+///
+/// Only links followed from the downloads page itself should get here, any page can link to them.
+pub fn action(path: &str, query: Option<&str>) {
+    if path == "/clear" {
+        downloads().retain(|d| d.state == State::Active);
+        changed();
+        return;
+    }
+
+    let Some(id) = query.and_then(|q| q.parse().ok()) else {
+        return;
+    };
+    let file = downloads().iter().find(|d| d.id == id).map(|d| d.path.clone());
+
+    let opened = match (path, file) {
+        ("/cancel", _) => {
+            cancel(id);
+            Ok(())
+        },
+        ("/retry", _) => {
+            retry(id);
+            Ok(())
+        },
+        ("/open", Some(file)) => handlers::open(file.as_os_str()),
+        ("/show", Some(file)) => handlers::open(file.parent().unwrap_or(&file).as_os_str()),
+        _ => Ok(()),
+    };
+    if let Err(err) = opened {
+        log::warn!("couldn't open download: {err}");
+    }
+}
+
+/// The `about://downloads` page, newest first.
+pub fn page() -> String {
+    let downloads = downloads();
+
+    let mut page = String::from("# Downloads\n\n");
+
+    if downloads.is_empty() {
+        page += "Nothing downloaded yet. Files Vostok can't show are saved here, or right click a link and choose \"Save link as\u{2026}\".\n";
+    }
+
+    for download in downloads.iter().rev() {
+        let name = download.path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        let size = viewer::format_size(download.received as usize);
+        page += &format!("\n## {name}\n");
+        page += &match &download.state {
+            State::Active => format!("Downloading, {size} so far\n"),
+            State::Finished => format!("{size}, saved to {}\n", download.path.display()),
+            State::Failed(err) => format!("Failed: {err}\n"),
+            State::Cancelled => "Cancelled\n".into(),
+        };
+        page += &format!("=> {} From {}\n", download.url, download.url);
+
+        let actions: &[(&str, &str)] = match download.state {
+            State::Active => &[("cancel", "Cancel")],
+            State::Finished => &[("open", "Open"), ("show", "Show in folder")],
+            State::Failed(_) | State::Cancelled => &[("retry", "Retry")],
+        };
+        for (action, label) in actions {
+            page += &format!("=> {PAGE_URL}/{action}?{} {label}\n", download.id);
+        }
+    }
+
+    if downloads.iter().any(|d| d.state != State::Active) {
+        page += &format!("\n=> {PAGE_URL}/clear Clear finished downloads from the list\n");
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn downloads_get_their_own_names() {
+        let url = |u| url::Url::parse(u).unwrap();
+        assert_eq!(file_name(&url("gemini://example.org/files/song%20one.ogg")), "song one.ogg");
+        assert_eq!(file_name(&url("gemini://example.org/a%2Fb.tar.gz")), "a_b.tar.gz");
+        assert_eq!(file_name(&url("gemini://example.org/")), "download");
+
+        let dir = std::env::temp_dir().join(format!("vostok-downloads-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("notes.tar.gz"), "").unwrap();
+        assert_eq!(unique_path(&dir, "notes.tar.gz"), dir.join("notes.tar (1).gz"));
+        assert_eq!(unique_path(&dir, "other"), dir.join("other"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(can_show("text/gemini; lang=en"));
+        assert!(can_show("image/png"));
+        assert!(!can_show("application/zip"));
+        assert!(!can_show("image/tiff"));
+    }
+}
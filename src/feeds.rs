@@ -0,0 +1,268 @@
+use std::{collections::{BTreeSet, HashSet}, sync::{atomic::{AtomicBool, Ordering}, Mutex, MutexGuard}, thread, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{gemini, gemtext, xmlfeed};
+
+pub const STORAGE_KEY: &str = "feeds";
+
+pub const PAGE_URL: &str = "about://feeds";
+
+/// Seconds between background refreshes of all subscriptions.
+const REFRESH_INTERVAL: u64 = 60 * 60;
+
+/// Largest feed read, anything bigger isn't a gemlog.
+const MAX_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A post listed in a feed.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Entry {
+    pub url: String,
+    /// `YYYY-MM-DD`, so entries sort by date as strings.
+    pub date: String,
+    pub title: String,
+}
+
+/// A page whose entries are collected into `about://feeds`.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Subscription {
+    pub url: String,
+    pub title: String,
+    pub entries: Vec<Entry>,
+    /// Why the last refresh failed, if it did.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Feeds {
+    subscriptions: Vec<Subscription>,
+    /// Urls of entries that have been opened.
+    read: BTreeSet<String>,
+    /// Unix time all subscriptions were last refreshed.
+    refreshed: u64,
+}
+
+static FEEDS: Mutex<Feeds> = Mutex::new(Feeds {
+    subscriptions: Vec::new(),
+    read: BTreeSet::new(),
+    refreshed: 0,
+});
+
+/// Set while a background refresh is running.
+static REFRESHING: AtomicBool = AtomicBool::new(false);
+
+fn feeds() -> MutexGuard<'static, Feeds> {
+    FEEDS.lock().expect("feeds lock poisoned")
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) {
+    if let Some(loaded) = storage.and_then(|storage| eframe::get_value(storage, STORAGE_KEY)) {
+        *feeds() = loaded;
+    }
+}
+
+pub fn save(storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, STORAGE_KEY, &*feeds());
+}
+
+pub fn is_subscribed(url: &url::Url) -> bool {
+    feeds().subscriptions.iter().any(|s| s.url == url.as_str())
+}
+
+/// Subscribe to the page at `url`, taking its current entries from `content`.
+pub fn subscribe(url: &url::Url, title: &str, content: &gemtext::GemText) {
+    let mut feeds = feeds();
+    if feeds.subscriptions.iter().any(|s| s.url == url.as_str()) {
+        return;
+    }
+
+    feeds.subscriptions.push(Subscription {
+        url: url.to_string(),
+        title: title.into(),
+        entries: parse_gemfeed(url, content),
+        error: None,
+    });
+}
+
+pub fn unsubscribe(url: &url::Url) {
+    feeds().subscriptions.retain(|s| s.url != url.as_str());
+}
+
+/// Mark the entry at `url` as read, if any subscription lists it.
+pub fn mark_read(url: &url::Url) {
+    let mut feeds = feeds();
+    let listed = feeds.subscriptions.iter()
+        .flat_map(|s| &s.entries)
+        .any(|e| e.url == url.as_str());
+
+    if listed {
+        feeds.read.insert(url.to_string());
+    }
+}
+
+pub fn mark_all_read() {
+    let mut feeds = feeds();
+    let urls: Vec<String> = feeds.subscriptions.iter()
+        .flat_map(|s| &s.entries)
+        .map(|e| e.url.clone())
+        .collect();
+    feeds.read.extend(urls);
+}
+
+/// Start refreshing all subscriptions in the background if it's been a while since the last refresh.
+pub fn refresh_if_due() {
+    if now().saturating_sub(feeds().refreshed) < REFRESH_INTERVAL {
+        return;
+    }
+    if REFRESHING.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    thread::spawn(|| {
+        refresh();
+        REFRESHING.store(false, Ordering::Release);
+    });
+}
+
+/// Fetch every subscription, blocking until they have all been updated.
+pub fn refresh() {
+    let urls: Vec<String> = feeds().subscriptions.iter().map(|s| s.url.clone()).collect();
+
+    for url in urls {
+        // don't hold the lock while waiting on the network
+        let fetched = url::Url::parse(&url).map_err(|err| err.to_string()).and_then(|url| fetch(&url));
+
+        let mut feeds = feeds();
+        // might have been unsubscribed in the meantime
+        let Some(subscription) = feeds.subscriptions.iter_mut().find(|s| s.url == url) else { continue; };
+
+        match fetched {
+            Ok((title, entries)) => {
+                if let Some(title) = title {
+                    subscription.title = title;
+                }
+                subscription.entries = entries;
+                subscription.error = None;
+            },
+            Err(err) => subscription.error = Some(err),
+        }
+    }
+
+    let mut feeds = feeds();
+    feeds.refreshed = now();
+
+    // forget read entries that are no longer listed anywhere
+    let listed: BTreeSet<String> = feeds.subscriptions.iter()
+        .flat_map(|s| &s.entries)
+        .map(|e| e.url.clone())
+        .collect();
+    feeds.read.retain(|url| listed.contains(url));
+}
+
+/// Request a feed, returning its title and entries.
+fn fetch(url: &url::Url) -> Result<(Option<String>, Vec<Entry>), String> {
+    let (url, mimetype, body) = gemini::fetch(url, MAX_SIZE)?;
+    let body = String::from_utf8_lossy(&body);
+
+    let mimetype = mimetype.split(';').next().unwrap_or_default().trim();
+    let content = if mimetype == "text/gemini" {
+        gemtext::GemText::new(&body)
+    } else if xmlfeed::is_feed(mimetype) {
+        let feed = xmlfeed::parse(&body).ok_or("Couldn't read feed")?;
+        gemtext::GemText::new(&xmlfeed::to_gemtext(&feed, &url))
+    } else {
+        return Err(format!("Unsupported feed type '{mimetype}'"));
+    };
+
+    let title = content.lines().find_map(|l| match l {
+        gemtext::GemLine::Heading(1, title) if !title.trim().is_empty() => Some(title.trim().to_owned()),
+        _ => None,
+    });
+    Ok((title, parse_gemfeed(&url, &content)))
+}
+
+/// Entries of a gemfeed, Atom and RSS feeds are converted to one by [`xmlfeed::to_gemtext`].
+///
+/// Entries are links whose text starts with an ISO 8601 date like `=> post.gmi 2024-05-21 - Title`.
+pub fn parse_gemfeed(url: &url::Url, content: &gemtext::GemText) -> Vec<Entry> {
+    content.lines()
+        .filter_map(|line| {
+            let gemtext::GemLine::Link(link, display) = line else { return None; };
+
+            let date = display.get(..10).filter(|d| is_date(d))?;
+            let title = display[10..].trim_start_matches(|c: char| c.is_whitespace() || matches!(c, '-' | '\u{2013}' | '\u{2014}' | ':'));
+
+            Some(Entry {
+                url: url.join(link).ok()?.to_string(),
+                date: date.into(),
+                title: if title.is_empty() { link.clone() } else { title.into() },
+            })
+        })
+        .collect()
+}
+
+fn is_date(s: &str) -> bool {
+    s.len() == 10 && s.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 => b == b'-',
+        _ => b.is_ascii_digit(),
+    })
+}
+
+/// The `about://feeds` page, every subscription's entries newest first.
+pub fn page() -> String {
+    let feeds = feeds();
+
+    let mut entries: Vec<(&Subscription, &Entry)> = feeds.subscriptions.iter()
+        .flat_map(|s| s.entries.iter().map(move |e| (s, e)))
+        .collect();
+    entries.sort_by(|(_, a), (_, b)| b.date.cmp(&a.date));
+    // the same post can be in more than one feed, only the first is kept
+    let mut seen = HashSet::new();
+    entries.retain(|(_, e)| seen.insert(e.url.as_str()));
+
+    let unread = entries.iter().filter(|(_, e)| !feeds.read.contains(&e.url)).count();
+
+    let mut page = String::from("# Feeds\n\n");
+
+    if feeds.subscriptions.is_empty() {
+        page += "You aren't subscribed to anything yet. Use the feed button next to the address bar to subscribe to a gemlog.\n";
+        return page;
+    }
+
+    let refreshed = if feeds.refreshed == 0 {
+        "never refreshed".to_owned()
+    } else {
+        match now().saturating_sub(feeds.refreshed) / 60 {
+            0 => "refreshed just now".to_owned(),
+            1 => "refreshed a minute ago".to_owned(),
+            n if n < 120 => format!("refreshed {n} minutes ago"),
+            n => format!("refreshed {} hours ago", n / 60),
+        }
+    };
+    page += &format!("{unread} unread, {refreshed}.\n");
+    page += &format!("=> {PAGE_URL}/refresh Refresh now\n");
+    page += &format!("=> {PAGE_URL}/read Mark all as read\n");
+
+    let mut date = "";
+    for (subscription, entry) in entries {
+        if entry.date != date {
+            date = &entry.date;
+            page += &format!("\n## {date}\n\n");
+        }
+        let marker = if feeds.read.contains(&entry.url) { "" } else { "\u{2022} " };
+        page += &format!("=> {} {marker}{}: {}\n", entry.url, subscription.title, entry.title);
+    }
+
+    page += "\n## Subscriptions\n\n";
+    for subscription in &feeds.subscriptions {
+        page += &format!("=> {} {}\n", subscription.url, subscription.title);
+        if let Some(error) = &subscription.error {
+            page += &format!("Last refresh failed: {error}\n");
+        }
+    }
+
+    page
+}
@@ -0,0 +1,179 @@
+use std::ops::Range;
+
+use crate::gemtext;
+
+/// A single search hit, as a byte range into the searchable text of a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Match {
+    pub line: usize,
+    pub range: Range<usize>,
+}
+
+/// Find in page state, lives for as long as the find bar is open.
+#[derive(Debug, Default)]
+pub struct Find {
+    pub query: String,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    current: usize,
+    matches: Vec<Match>,
+    /// Set when the current match changed and should be scrolled into view.
+    scroll_pending: bool,
+    /// (query, case sensitive, whole word, content id, line count) the matches were computed for.
+    searched: Option<(String, bool, bool, Option<u64>, usize)>,
+}
+
+impl Find {
+    /// Recompute matches if the query, options or page changed since the last search.
+    pub fn update(&mut self, content: &gemtext::GemText) {
+        let key = (self.query.clone(), self.case_sensitive, self.whole_word, content.id(), content.lines().len());
+
+        if self.searched.as_ref() == Some(&key) {
+            return;
+        }
+
+        // more lines of a page that's still arriving keep the current match where it is
+        let grown = self.searched.as_ref().is_some_and(|(query, case_sensitive, whole_word, id, _)| {
+            (query, *case_sensitive, *whole_word, *id) == (&key.0, key.1, key.2, key.3)
+        });
+
+        self.matches = content.find(&self.query, self.case_sensitive, self.whole_word);
+        if !grown {
+            self.current = 0;
+            self.scroll_pending = !self.matches.is_empty();
+        }
+        self.searched = Some(key);
+    }
+
+    pub fn next(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + 1) % self.matches.len();
+            self.scroll_pending = true;
+        }
+    }
+
+    pub fn previous(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + self.matches.len() - 1) % self.matches.len();
+            self.scroll_pending = true;
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Index of the current match, if there are any matches.
+    pub fn current(&self) -> Option<usize> {
+        (!self.matches.is_empty()).then_some(self.current)
+    }
+
+    /// Matches on the given line, paired with whether they are the current match.
+    pub fn matches_on(&self, line: usize) -> Vec<(Range<usize>, bool)> {
+        let start = self.matches.partition_point(|m| m.line < line);
+        self.matches[start..].iter()
+            .enumerate()
+            .take_while(|(_, m)| m.line == line)
+            .map(|(i, m)| (m.range.clone(), start + i == self.current))
+            .collect()
+    }
+
+    /// Line holding the current match, if it still needs scrolling to.
+    pub fn scroll_target(&self) -> Option<usize> {
+        self.scroll_pending.then(|| self.matches.get(self.current).map(|m| m.line)).flatten()
+    }
+
+    /// Returns true once if the line holding the current match should be scrolled into view.
+    pub fn take_scroll(&mut self, line: usize) -> bool {
+        if self.scroll_pending && self.matches.get(self.current).is_some_and(|m| m.line == line) {
+            self.scroll_pending = false;
+            return true;
+        }
+        false
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Find all non-overlapping occurrences of `needle` in `haystack`, returning byte ranges.
+pub fn find_all(haystack: &str, needle: &str, case_sensitive: bool, whole_word: bool) -> Vec<Range<usize>> {
+    let needle: Vec<char> = needle.chars().collect();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+
+    let chars: Vec<(usize, char)> = haystack.char_indices().collect();
+    let eq = |a: char, b: char| if case_sensitive {
+        a == b
+    } else {
+        a == b || a.to_lowercase().eq(b.to_lowercase())
+    };
+
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i + needle.len() <= chars.len() {
+        let found = needle.iter().enumerate().all(|(j, n)| eq(chars[i + j].1, *n));
+
+        if found {
+            let end = i + needle.len();
+            let bounded = !whole_word || (
+                (i == 0 || !is_word_char(chars[i - 1].1)) &&
+                chars.get(end).is_none_or(|(_, c)| !is_word_char(*c))
+            );
+
+            if bounded {
+                out.push(chars[i].0..chars.get(end).map_or(haystack.len(), |(b, _)| *b));
+                i = end;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_are_found() {
+        assert_eq!(find_all("Gemini gemini GEMINI", "gemini", false, false), [0..6, 7..13, 14..20]);
+        assert_eq!(find_all("Gemini gemini GEMINI gemini", "gemini", true, false), [7..13, 21..27]);
+
+        // words at the very start and end of the line count as whole
+        assert_eq!(find_all("cap capsule cap", "cap", false, true), [0..3, 12..15]);
+        assert_eq!(find_all("recap_ cap cap", "cap", false, true), [7..10, 11..14]);
+
+        // ranges are bytes, and case folding works beyond ASCII
+        assert_eq!(find_all("Straße STRASSE ÉTÉ été", "été", false, false), [16..21, 22..27]);
+        assert_eq!(find_all("日本語の日本", "日本", true, false), [0..6, 12..18]);
+
+        // matches don't overlap
+        assert_eq!(find_all("aaaa", "aa", true, false), [0..2, 2..4]);
+        assert!(find_all("anything", "", false, false).is_empty());
+    }
+
+    #[test]
+    fn lines_arriving_keep_the_current_match() {
+        let mut parser = gemtext::Parser::default();
+        let mut content = gemtext::GemText::default();
+        content.extend(parser.push(b"gemini\ngemini\n"));
+
+        let mut find = Find { query: "gemini".into(), ..Default::default() };
+        find.update(&content);
+        find.next();
+        find.scroll_pending = false;
+
+        content.extend(parser.push(b"more gemini\n"));
+        find.update(&content);
+        assert_eq!(find.matches.len(), 3);
+        assert_eq!(find.current, 1);
+        assert!(!find.scroll_pending);
+    }
+}
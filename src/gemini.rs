@@ -1,10 +1,15 @@
-use std::{io::{self, Read, Write}, net::TcpStream, sync::Arc};
+use std::{io::{self, Read, Write}, net::{TcpStream, ToSocketAddrs}, sync::Arc, time::{Duration, Instant}};
 
 use rustls::{client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier}, pki_types::{DnsName, IpAddr, ServerName}};
+use sha2::Digest;
+
+use crate::tofu;
 
 pub const SCHEME: &str = "gemini";
 pub const PORT: u16 = 1965;
 
+const MAX_REDIRECTS: usize = 5;
+
 /// Trust on first use certificate verifier. Currently just accepts all.
 #[derive(Debug)]
 pub struct Tofu; // todo: verify certs
@@ -58,72 +63,251 @@ impl ServerCertVerifier for Tofu {
     }
 }
 
-pub fn request(url: &url::Url) -> io::Result<Response> {
+/// Longest header allowed, a two digit status, a space, 1024 bytes of meta and CRLF.
+const MAX_HEADER: usize = 1029;
+
+/// The certificate a server presented.
+#[derive(Debug, Clone)]
+pub struct Certificate {
+    pub subject: String,
+    pub issuer: String,
+    /// Unix times it's valid between.
+    pub not_before: i64,
+    pub not_after: i64,
+    /// SHA-256 of the certificate as colon separated hex.
+    pub fingerprint: String,
+    pub trust: tofu::Trust,
+}
+
+impl Certificate {
+    /// Parse the certificate `host` presented, checking it against the one it presented before.
+    fn new(der: &[u8], host: &str) -> Option<Certificate> {
+        let (_, certificate) = x509_parser::parse_x509_certificate(der).ok()?;
+        let validity = certificate.validity();
+        let not_after = validity.not_after.timestamp();
+        let fingerprint = sha2::Sha256::digest(der).iter().map(|b| format!("{b:02X}")).collect::<Vec<_>>().join(":");
+
+        Some(Certificate {
+            subject: certificate.subject().to_string(),
+            issuer: certificate.issuer().to_string(),
+            not_before: validity.not_before.timestamp(),
+            not_after,
+            trust: tofu::check(host, &fingerprint, not_after, x509_parser::time::ASN1Time::now().timestamp()),
+            fingerprint,
+        })
+    }
+}
+
+/// Time from starting a request until each step of it finished.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timing {
+    pub dns: Duration,
+    pub connect: Duration,
+    pub handshake: Duration,
+    pub first_byte: Duration,
+    /// Until the whole body was read.
+    pub total: Duration,
+}
+
+/// How a response arrived, for the page info.
+#[derive(Debug, Clone, Default)]
+pub struct Info {
+    /// Like `TLS 1.3`.
+    pub tls_version: String,
+    pub cipher: String,
+    /// `None` if the server didn't send one that could be parsed.
+    pub certificate: Option<Certificate>,
+    pub timing: Timing,
+    /// Bytes received, the header and body.
+    pub size: usize,
+}
+
+/// A response whose header has been read, with the body left to read as it arrives.
+pub struct Stream {
+    pub status: u8,
+    pub meta: String,
+    /// Complete once the body is read, see [`Stream::into_raw`].
+    pub info: Info,
+    /// The status line as it was sent, including its CRLF.
+    header: Vec<u8>,
+    started: Instant,
+    connection: rustls::StreamOwned<rustls::ClientConnection, TcpStream>,
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.connection.read(buf)
+    }
+}
+
+/// Send a request for `url` and read the header of the response.
+pub fn open(url: &url::Url) -> io::Result<Stream> {
     log::debug!("requesting {url}");
 
-    if url.scheme() == SCHEME {
-        if url.has_host() {
-            let host_str = url.host_str().expect("unreachable");
-            let host = url.host().expect("unreachable");
+    if url.scheme() == SCHEME && url.has_host() {
+        let host_str = url.host_str().expect("unreachable");
+        let host = url.host().expect("unreachable");
 
-            let mut socket = TcpStream::connect((host_str, url.port_or_known_default().unwrap_or(PORT)))?;
+        let port = url.port_or_known_default().unwrap_or(PORT);
+        let started = Instant::now();
+        let mut info = Info::default();
 
-            let config = rustls::ClientConfig::builder().dangerous()
-                .with_custom_certificate_verifier(Arc::new(Tofu))
-                .with_no_client_auth();
+        let addresses: Vec<_> = (host_str, port).to_socket_addrs()?.collect();
+        info.timing.dns = started.elapsed();
+        let socket = TcpStream::connect(&addresses[..])?;
+        info.timing.connect = started.elapsed();
 
-            let name = match host {
-                url::Host::Domain(domain) => ServerName::DnsName(DnsName::try_from(domain.to_owned()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?),
-                url::Host::Ipv4(ip) => ServerName::IpAddress(IpAddr::V4(ip.into())),
-                url::Host::Ipv6(ip) => ServerName::IpAddress(IpAddr::V6(ip.into())),
-            };
-            
-            let mut client = rustls::ClientConnection::new(Arc::new(config), name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
-            let mut stream = rustls::Stream::new(&mut client, &mut socket);
+        let config = rustls::ClientConfig::builder().dangerous()
+            .with_custom_certificate_verifier(Arc::new(Tofu))
+            .with_no_client_auth();
 
-            stream.write_all(format!("{url}\r\n").as_bytes())?;
-            
-            let mut response = Vec::new();
+        let name = match host {
+            url::Host::Domain(domain) => ServerName::DnsName(DnsName::try_from(domain.to_owned()).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?),
+            url::Host::Ipv4(ip) => ServerName::IpAddress(IpAddr::V4(ip.into())),
+            url::Host::Ipv6(ip) => ServerName::IpAddress(IpAddr::V6(ip.into())),
+        };
+        
+        let client = rustls::ClientConnection::new(Arc::new(config), name).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let mut connection = rustls::StreamOwned::new(client, socket);
 
-            stream.read_to_end(&mut response)?;
+        while connection.conn.is_handshaking() {
+            connection.conn.complete_io(&mut connection.sock)?;
+        }
+        info.timing.handshake = started.elapsed();
+        info.tls_version = match connection.conn.protocol_version() {
+            Some(rustls::ProtocolVersion::TLSv1_2) => "TLS 1.2".into(),
+            Some(rustls::ProtocolVersion::TLSv1_3) => "TLS 1.3".into(),
+            version => version.map(|v| format!("{v:?}")).unwrap_or_default(),
+        };
+        info.cipher = connection.conn.negotiated_cipher_suite().map(|c| format!("{:?}", c.suite())).unwrap_or_default();
+        info.certificate = connection.conn.peer_certificates()
+            .and_then(|certificates| certificates.first())
+            .and_then(|der| Certificate::new(der, &format!("{host_str}:{port}")));
 
-            let response = String::from_utf8(response).map_err(|_| io::ErrorKind::InvalidData)?;
-            let response = RawResponse::try_from(response)?;
-            
-            return Response::try_from(response);
+        connection.write_all(format!("{url}\r\n").as_bytes())?;
+
+        // a byte at a time, so none of the body is read
+        let mut header = Vec::new();
+        let mut byte = [0];
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= MAX_HEADER || connection.read(&mut byte)? == 0 {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            if header.is_empty() {
+                info.timing.first_byte = started.elapsed();
+            }
+            header.push(byte[0]);
         }
+        info.size = header.len();
+
+        let RawResponse { status, meta, .. } = RawResponse::try_from(header.clone())?;
+        return Ok(Stream { status, meta, info, header, started, connection });
     }
 
     Err(io::ErrorKind::Unsupported.into())
 }
 
+impl Stream {
+    /// Give up on reads that wait longer than `timeout` for the server, see [`TcpStream::set_read_timeout`].
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.connection.sock.set_read_timeout(timeout)
+    }
+
+    /// Read the rest of the body.
+    pub fn into_raw(mut self) -> io::Result<(RawResponse, Info)> {
+        let mut body = Vec::new();
+        self.read_to_end(&mut body)?;
+        Ok(self.finish(body))
+    }
+
+    /// The response once `body`, everything that was read from it, has arrived.
+    pub fn finish(mut self, body: Vec<u8>) -> (RawResponse, Info) {
+        self.info.timing.total = self.started.elapsed();
+        self.info.size += body.len();
+
+        (RawResponse {
+            status: self.status,
+            meta: self.meta,
+            header: self.header,
+            body: body.into(),
+        }, self.info)
+    }
+}
+
+/// Request `url` following any redirects, returning the final url and the successful response.
+///
+/// Anything other than success is an error, including requests for input.
+pub fn open_following(url: &url::Url) -> Result<(url::Url, Stream), String> {
+    let mut url = url.clone();
+
+    for _ in 0..MAX_REDIRECTS {
+        let stream = open(&url).map_err(|err| err.to_string())?;
+
+        match stream.status / 10 {
+            2 => return Ok((url, stream)),
+            3 => url = url.join(&stream.meta).map_err(|err| err.to_string())?,
+            1 => return Err(format!("{} Asks for input", stream.status)),
+            _ => return Err(format!("{} {}", stream.status, stream.meta)),
+        }
+    }
+
+    Err("Too many redirects".into())
+}
+
+/// Request `url` following any redirects, returning the final url, mimetype and body.
+///
+/// Anything other than success is an error, including requests for input and bodies over `max_size` bytes.
+pub fn fetch(url: &url::Url, max_size: u64) -> Result<(url::Url, String, Vec<u8>), String> {
+    let (url, stream) = open_following(url)?;
+
+    let mut body = Vec::new();
+    let mut stream = stream.take(max_size + 1);
+    stream.read_to_end(&mut body).map_err(|err| err.to_string())?;
+    if body.len() as u64 > max_size {
+        return Err(format!("Larger than {max_size} bytes"));
+    }
+
+    Ok((url, stream.into_inner().meta, body))
+}
+
 // 20 text/gemini\r\n# Project Gemini\n\n## Gemini in 100 words\n\nGemini is a new internet technology supporting an electronic library of interconnected text documents.  That's not a new idea, but it's not old fashioned either.  It's timeless, and deserves tools which treat it as a first class concept, not a vestigial corner case.  Gemini isn't about innovation or disruption, it's about providing some respite for those who feel the internet has been disrupted enough already.  We're not out to change the world or destroy other technologies.  We are out to build a lightweight online space where documents are just documents, in the interests of every reader's privacy, attention and bandwidth.\n\n=> docs/faq.gmi\tIf you'd like to know more, read our FAQ\n=> https://www.youtube.com/watch?v=DoEI6VzybDk\tOr, if you'd prefer, here's a video overview\n\n## Official resources\n\n=> news/\tProject Gemini news\n=> docs/\tProject Gemini documentation\n=> history/\tProject Gemini history\n=> software/\tKnown Gemini software\n\nAll content at geminiprotocol.net is CC BY-NC-ND 4.0 licensed unless stated otherwise:\n=> https://creativecommons.org/licenses/by-nc-nd/4.0/\tCC Attribution-NonCommercial-NoDerivs 4.0 International\n
-struct RawResponse {
-    status: u8,
-    meta: String,
-    body: String,
+/// A response as it was sent, before the status is interpreted.
+#[derive(Debug, Clone)]
+pub struct RawResponse {
+    pub status: u8,
+    pub meta: String,
+    /// The status line as it was sent, including its CRLF.
+    pub header: Vec<u8>,
+    /// Shared with the page shown from it, like an image viewer.
+    pub body: Arc<[u8]>,
 }
 
-impl TryFrom<String> for RawResponse {
+impl TryFrom<Vec<u8>> for RawResponse {
     type Error = io::Error;
 
-    fn try_from(mut value: String) -> io::Result<RawResponse> {
-        let mut meta = value.split_off(2);
-        let status = value.parse().map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+    fn try_from(mut value: Vec<u8>) -> io::Result<RawResponse> {
+        // only the header is text, the body can be anything
+        let crlf = value.windows(2).position(|w| w == b"\r\n").ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
+        let body = value.split_off(crlf+2);
+        let original = value.clone();
+        value.truncate(crlf);
+
+        let mut header = String::from_utf8(value).map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+        if !header.is_char_boundary(2) {
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let mut meta = header.split_off(2);
+        let status = header.parse().map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
 
         if meta.starts_with(' ') {
             meta = meta.split_off(1);
         }
         
-        let crlf = meta.find("\r\n").ok_or(io::Error::from(io::ErrorKind::InvalidData))?;
-        let body = meta.split_off(crlf+2);
-        // remove CRLF
-        meta.pop(); meta.pop();
-        
         Ok(RawResponse {
             status,
             meta,
-            body,
+            header: original,
+            body: body.into(),
         })
     }
 }
@@ -144,19 +328,20 @@ impl Response {
     }
 }
 
-impl TryFrom<RawResponse> for Response {
+impl TryFrom<&RawResponse> for Response {
     type Error = io::Error;
 
-    fn try_from(value: RawResponse) -> io::Result<Response> {
+    fn try_from(value: &RawResponse) -> io::Result<Response> {
+        let meta = value.meta.clone();
         Ok(Response {
             status: value.status,
             content: match value.status / 10 {
-                1 => ResponseContent::InputExpected { prompt: value.meta },
-                2 => ResponseContent::Success { mimetype: value.meta, body: value.body },
-                3 => ResponseContent::Redirection { uri: value.meta },
-                4 => ResponseContent::TemporaryFailure { error: value.meta },
-                5 => ResponseContent::PermanentFailure { error: value.meta },
-                6 => ResponseContent::ClientCertifiates { error: value.meta },
+                1 => ResponseContent::InputExpected { prompt: meta },
+                2 => ResponseContent::Success { mimetype: meta, body: value.body.clone() },
+                3 => ResponseContent::Redirection { uri: meta },
+                4 => ResponseContent::TemporaryFailure { error: meta },
+                5 => ResponseContent::PermanentFailure { error: meta },
+                6 => ResponseContent::ClientCertifiates { error: meta },
                 _ => return Err(io::ErrorKind::InvalidData.into()),
             },
         })
@@ -170,7 +355,7 @@ pub enum ResponseContent {
     },
     Success {
         mimetype: String,
-        body: String,
+        body: Arc<[u8]>,
     },
     Redirection {
         uri: String,
@@ -185,3 +370,23 @@ pub enum ResponseContent {
         error: String,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodies_can_be_binary() {
+        let mut raw = b"20 image/png\r\n".to_vec();
+        raw.extend_from_slice(&[0x89, b'P', b'N', b'G', 0xff, 0x00]);
+
+        let raw = RawResponse::try_from(raw).unwrap();
+        assert_eq!(raw.header, b"20 image/png\r\n");
+        let response = Response::try_from(&raw).unwrap();
+
+        assert_eq!(response.status(), 20);
+        let ResponseContent::Success { mimetype, body } = response.content() else { panic!("expected success") };
+        assert_eq!(mimetype, "image/png");
+        assert_eq!(body[..], [0x89, b'P', b'N', b'G', 0xff, 0x00]);
+    }
+}
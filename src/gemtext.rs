@@ -1,117 +1,1279 @@
-use std::sync::atomic::AtomicU64;
+use std::{cell::RefCell, ops::Range, sync::atomic::AtomicU64};
 
-use crate::gemini;
+use crate::{ansi, bidi, bookmarks, find, gemini, hints, images, markdown, plaintext, selection, settings, source, syntax, theme, visited};
 
-#[derive(Debug)]
-struct Preformatted {
+#[derive(Debug, PartialEq)]
+pub struct Preformatted {
     alt: String,
+    /// Contents with any escape sequences removed.
     contents: String,
+    /// Formatting from ANSI escapes in the contents, if there were any.
+    ansi: Vec<(Range<usize>, ansi::Format)>,
 }
 
-#[derive(Debug)]
-enum GemLine {
+impl Preformatted {
+    /// A block with `alt` text, taking formatting from any ANSI escapes in the contents.
+    pub fn new(alt: &str, contents: &str) -> Preformatted {
+        let (contents, ansi) = if ansi::has_escapes(contents) { ansi::parse(contents) } else { (contents.into(), Vec::new()) };
+        Preformatted {
+            alt: alt.into(),
+            contents,
+            ansi,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GemLine {
     Text(String),
     Heading(u8, String),
+    /// Url and the text shown for it, which is the url if the line didn't give any.
     Link(String, String),
+    ListItem(String),
+    Quote(String),
     Preformatted(Preformatted),
+    /// A line of a plain text document, see [`GemText::plain`].
+    Plain(plaintext::Line),
+    /// A block of a markdown document, see [`GemText::markdown`].
+    Markdown(markdown::Block),
+    /// A line of a page's source, see [`GemText::view_source`].
+    Source(plaintext::Line, source::Kind),
+}
+
+impl GemLine {
+    /// The text of the line as shown to the reader, used for searching.
+    pub fn text(&self) -> &str {
+        match self {
+            GemLine::Text(text) => text,
+            GemLine::Heading(_, text) => text,
+            GemLine::Link(_, display) => display,
+            GemLine::ListItem(text) => text,
+            GemLine::Quote(text) => text,
+            GemLine::Preformatted(pf) => &pf.contents,
+            GemLine::Plain(line) | GemLine::Source(line, _) => &line.text,
+            GemLine::Markdown(block) => block.text(),
+        }
+    }
+
+    /// Byte ranges of [`GemLine::text`] that are links, and their urls as written.
+    pub fn links(&self) -> Vec<(Range<usize>, &str)> {
+        match self {
+            GemLine::Link(url, display) => vec![(0..display.len(), url.as_str())],
+            GemLine::Plain(line) | GemLine::Source(line, _) => line.links.iter().map(|l| (l.clone(), &line.text[l.clone()])).collect(),
+            GemLine::Markdown(block) => block.links(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Rough height of the line including item spacing, for lines that haven't been laid out yet.
+    fn estimate_height(&self, ui: &egui::Ui, settings: &settings::Settings) -> f32 {
+        let typography = &settings.typography;
+        let spacing = ui.spacing().item_spacing.y;
+
+        let (font_id, text): (egui::FontId, &str) = match self {
+            GemLine::Text(text) => (typography.body_font(), text),
+            GemLine::Heading(n, text) => (typography.heading_font(*n), text),
+            GemLine::Link(_, display) => (typography.body_font(), display),
+            GemLine::ListItem(text) => (typography.body_font(), text),
+            GemLine::Quote(text) => (typography.body_font(), text),
+            GemLine::Preformatted(pf) => {
+                let font_id = typography.preformatted_font();
+                let rows = pf.contents.lines().count().max(1) as f32;
+                return rows * ui.fonts(|f| f.row_height(&font_id)) + spacing;
+            },
+            GemLine::Plain(line) | GemLine::Source(line, _) => {
+                let font_id = typography.preformatted_font();
+                let page_break = if line.page_break { ui.spacing().item_spacing.y * 2.0 + 1.0 } else { 0.0 };
+                if !settings.plain_text_wrap {
+                    return ui.fonts(|f| f.row_height(&font_id)) + spacing + page_break;
+                }
+                (font_id, &line.text)
+            },
+            GemLine::Markdown(markdown::Block::Heading(n, text)) => (typography.heading_font(*n), &text.text),
+            GemLine::Markdown(markdown::Block::Table(table)) => {
+                let font_id = typography.body_font();
+                let rows = (table.rows.len() + 1) as f32;
+                return rows * (ui.fonts(|f| f.row_height(&font_id)) + ui.spacing().interact_size.y * 0.25) + spacing;
+            },
+            GemLine::Markdown(block) => (typography.body_font(), block.text()),
+        };
+
+        let row_height = typography.line_height(ui, &font_id).unwrap_or_else(|| ui.fonts(|f| f.row_height(&font_id)));
+        // assume an average glyph is half as wide as it is tall
+        let text_width = text.chars().count() as f32 * font_id.size * 0.5;
+        let rows = (text_width / ui.available_width().max(1.0)).ceil().max(1.0);
+
+        rows * row_height + spacing
+    }
+
+    /// Gemtext for the part of the line in `range`, a byte range of [`GemLine::text`].
+    fn source(&self, range: Range<usize>) -> String {
+        let text = &self.text()[range];
+        match self {
+            GemLine::Text(_) => text.into(),
+            GemLine::Heading(n, _) => format!("{} {text}", "#".repeat(*n as usize)),
+            GemLine::Link(url, display) if display == url => format!("=> {url}"),
+            GemLine::Link(url, _) => format!("=> {url} {text}").trim_end().into(),
+            GemLine::ListItem(_) => format!("* {text}"),
+            GemLine::Quote(_) => format!("> {text}"),
+            GemLine::Preformatted(pf) => format!("```{}\n{text}\n```", pf.alt),
+            GemLine::Plain(_) | GemLine::Source(..) => text.into(),
+            GemLine::Markdown(markdown::Block::Heading(n, _)) => format!("{} {text}", "#".repeat((*n).min(3) as usize)),
+            GemLine::Markdown(markdown::Block::ListItem { .. }) => format!("* {text}"),
+            GemLine::Markdown(markdown::Block::Quote(..)) => format!("> {text}"),
+            GemLine::Markdown(_) => text.into(),
+        }
+    }
+
+    fn render(&self, ui: &mut egui::Ui, state: &mut RenderState, line: usize, matches: &[(Range<usize>, bool)], hint: &str) -> egui::Response {
+        let settings = state.settings;
+        let typography = &settings.typography;
+        // line spacing only applies to prose, preformatted text keeps its rows together
+        let text_format = |font_id: egui::FontId| egui::TextFormat {
+            line_height: typography.line_height(ui, &font_id),
+            ..egui::TextFormat::simple(font_id, egui::Color32::PLACEHOLDER)
+        };
+
+        match self {
+            GemLine::Text(text) => {
+                let job = layout(ui, "", text, text_format(typography.body_font()), matches);
+                state.label(ui, line, text.len(), job, state.theme.text, false)
+            },
+            GemLine::Heading(n, text) => {
+                let job = layout(ui, "", text, text_format(typography.heading_font(*n)), matches);
+                state.label(ui, line, text.len(), job, state.theme.heading(*n), false)
+            },
+            GemLine::Link(url, display) => {
+                let target = state.base.join(url).ok();
+                let external = url.starts_with("http://") || url.starts_with("https://");
+                let colour = state.link_colour(url);
+
+                let icon = if external { "\u{1F310} " } else { "\u{1F680} " };
+                let job = with_hint(ui, layout(ui, icon, display, text_format(typography.body_font()), matches), hint, state.hint_typed);
+                let response = state.label(ui, line, display.len(), job, colour, true);
+
+                let new_tab = response.middle_clicked() || (response.clicked() && ui.input(|i| i.modifiers.command));
+                if new_tab {
+                    state.links.followed = Some((url.into(), Target::NewTab));
+                } else if response.clicked() {
+                    state.links.followed = Some((url.into(), Target::Current));
+                }
+                if response.hovered() {
+                    state.links.hovered = Some(state.resolve(url));
+                }
+
+                let host = state.base.host_str().unwrap_or_default();
+                if let Some(image) = target.filter(images::is_image_link) {
+                    if state.settings.inline_images_for(host) {
+                        images::show(ui, &image);
+                    }
+                }
+                response
+            }
+            GemLine::ListItem(text) => {
+                let job = layout(ui, "\u{2022} ", text, text_format(typography.body_font()), matches);
+                state.label(ui, line, text.len(), job, state.theme.text, false)
+            },
+            GemLine::Quote(text) => {
+                let job = layout(ui, "", text, text_format(typography.body_font()), matches);
+                let colour = state.theme.quote;
+                // the bar goes on the side the quote starts from
+                let rtl = bidi::is_rtl(text, state.rtl);
+                let margin = if rtl { egui::Margin { right: 12.0, ..Default::default() } } else { egui::Margin { left: 12.0, ..Default::default() } };
+                let response = egui::Frame::none().inner_margin(margin).show(ui, |ui| {
+                    state.label(ui, line, text.len(), job, colour, false)
+                });
+                let rect = response.response.rect;
+                let x = if rtl { rect.right() - 2.0 } else { rect.left() + 2.0 };
+                ui.painter().vline(x, rect.y_range(), egui::Stroke::new(3.0, colour.gamma_multiply(0.5)));
+                response.inner
+            },
+            GemLine::Preformatted(Preformatted {
+                alt,
+                contents,
+                ansi,
+            }) => {
+                // a block holding the current match is always shown
+                let has_current = matches.iter().any(|(_, current)| *current);
+                let collapsed_id = ui.id().with("collapsed");
+                let collapsed = ui.data(|d| d.get_temp::<bool>(collapsed_id))
+                    .unwrap_or(state.settings.collapse_preformatted) && !has_current;
+
+                let alt = alt.trim();
+
+                ui.vertical(|ui| {
+                    if !alt.is_empty() || collapsed {
+                        let caption = if alt.is_empty() { "Preformatted text" } else { alt };
+                        let toggle = ui.horizontal(|ui| {
+                            let icon = egui::RichText::new(if collapsed { "\u{f0da}" } else { "\u{f0d7}" })
+                                .family(egui::FontFamily::Name("icons".into()))
+                                .small()
+                                .weak();
+                            ui.add(egui::Label::new(icon).selectable(false).sense(egui::Sense::click())).clicked() |
+                                ui.add(egui::Label::new(egui::RichText::new(caption).small().weak().italics()).sense(egui::Sense::click()))
+                                    .on_hover_text(if collapsed { "Show preformatted text" } else { "Collapse to alt text" })
+                                    .clicked()
+                        }).inner;
+                        if toggle {
+                            ui.data_mut(|d| d.insert_temp(collapsed_id, !collapsed));
+                        }
+                    }
+
+                    if collapsed {
+                        return;
+                    }
+
+                    let font_id = typography.preformatted_font();
+                    let job = if !ansi.is_empty() && state.settings.ansi_colours {
+                        mark_matches(ui, ansi::layout(contents, ansi, &font_id, ui.visuals()), 0, matches)
+                    } else if let Some(syntax) = syntax::syntax_for(alt) {
+                        mark_matches(ui, syntax::highlight(ui, contents, syntax, &font_id), 0, matches)
+                    } else {
+                        layout(ui, "", contents, egui::TextFormat::simple(font_id, egui::Color32::PLACEHOLDER), matches)
+                    };
+                    let response = egui::ScrollArea::horizontal().show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            selection::label(ui, &mut state.texts, line, contents.len(), job, state.theme.preformatted, false)
+                        }).inner
+                    }).inner;
+
+                    if !alt.is_empty() {
+                        // screen readers announce the description rather than the art itself
+                        ui.ctx().accesskit_node_builder(response.id, |node| node.set_name(alt));
+                        response.on_hover_text(alt);
+                    }
+                }).response
+            },
+            GemLine::Plain(plain) => state.plain_label(ui, line, plain, matches, state.theme.preformatted),
+            GemLine::Source(plain, kind) => {
+                let colour = match kind {
+                    source::Kind::Header | source::Kind::Toggle => ui.visuals().weak_text_color(),
+                    source::Kind::Text | source::Kind::ListItem => state.theme.text,
+                    source::Kind::Heading(n) => state.theme.heading(*n),
+                    source::Kind::Link => state.theme.gemini_link,
+                    source::Kind::Quote => state.theme.quote,
+                    source::Kind::Preformatted | source::Kind::Binary => state.theme.preformatted,
+                };
+                state.plain_label(ui, line, plain, matches, colour)
+            },
+            GemLine::Markdown(markdown::Block::Rule) => ui.separator(),
+            GemLine::Markdown(markdown::Block::Table(table)) => {
+                let format = text_format(typography.body_font());
+                let links = self.links();
+                ui.vertical(|ui| {
+                    egui::ScrollArea::horizontal().show(ui, |ui| {
+                        egui::Grid::new("table").striped(true).spacing([16.0, 4.0]).show(ui, |ui| {
+                            let mut last_row = 0;
+                            for (r, c, start, cell) in table.cells() {
+                                if r != last_row {
+                                    ui.end_row();
+                                    last_row = r;
+                                }
+                                // each cell is laid out on its own, from its part of the table's text
+                                let end = start + cell.text.len();
+                                let cell_matches: Vec<(Range<usize>, bool)> = matches.iter()
+                                    .filter(|(range, _)| range.start < end && range.end > start)
+                                    .map(|(range, current)| (range.start.max(start) - start..range.end.min(end) - start, *current))
+                                    .collect();
+                                let job = inline_layout(ui, state, "", cell, format.clone(), &cell_matches);
+                                let colour = if r == 0 { state.theme.heading(3) } else { state.theme.text };
+                                let align = match table.alignments.get(c) {
+                                    Some(markdown::Alignment::Right) => egui::Align::Max,
+                                    Some(markdown::Alignment::Center) => egui::Align::Center,
+                                    _ => egui::Align::Min,
+                                };
+                                let at = selection::Position { line, offset: start };
+                                ui.with_layout(egui::Layout::top_down(align), |ui| state.linked_label(ui, at, cell.text.len(), job, colour, &links));
+                            }
+                            ui.end_row();
+                        });
+                    });
+                }).response
+            },
+            GemLine::Markdown(markdown::Block::Heading(n, text)) => {
+                let job = inline_layout(ui, state, "", text, text_format(typography.heading_font(*n)), matches);
+                state.linked_label(ui, line, text.text.len(), job, state.theme.heading(*n), &text.links())
+            },
+            GemLine::Markdown(markdown::Block::ListItem { depth, marker, text }) => {
+                // later paragraphs of an item line up with its text rather than its marker
+                let indent = 16.0 * (*depth - 1) as f32 + if marker.is_empty() { 16.0 } else { 0.0 };
+                let prefix = if marker.is_empty() { String::new() } else { format!("{marker} ") };
+                let job = inline_layout(ui, state, &prefix, text, text_format(typography.body_font()), matches);
+                egui::Frame::none().inner_margin(egui::Margin { left: indent, ..Default::default() }).show(ui, |ui| {
+                    state.linked_label(ui, line, text.text.len(), job, state.theme.text, &text.links())
+                }).inner
+            },
+            GemLine::Markdown(markdown::Block::Quote(depth, text)) => {
+                let job = inline_layout(ui, state, "", text, text_format(typography.body_font()), matches);
+                let colour = state.theme.quote;
+                let response = egui::Frame::none().inner_margin(egui::Margin { left: 12.0 * *depth as f32, ..Default::default() }).show(ui, |ui| {
+                    state.linked_label(ui, line, text.text.len(), job, colour, &text.links())
+                });
+                let rect = response.response.rect;
+                for i in 0..*depth {
+                    ui.painter().vline(rect.left() + 2.0 + 12.0 * i as f32, rect.y_range(), egui::Stroke::new(3.0, colour.gamma_multiply(0.5)));
+                }
+                response.inner
+            },
+            GemLine::Markdown(markdown::Block::Paragraph(text)) => {
+                let job = inline_layout(ui, state, "", text, text_format(typography.body_font()), matches);
+                state.linked_label(ui, line, text.text.len(), job, state.theme.text, &text.links())
+            },
+        }
+    }
+}
+
+/// Where to open a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Current,
+    NewTab,
+    /// A new tab, without switching to it.
+    BackgroundTab,
+    /// Saved to the downloads folder.
+    Download,
+}
+
+/// What the reader did with links during a frame.
+#[derive(Debug, Default)]
+pub struct LinkEvents {
+    /// A link to open, as written in the page.
+    pub followed: Option<(String, Target)>,
+    /// Absolute url of the link under the pointer.
+    pub hovered: Option<String>,
+}
+
+/// Everything lines need while rendering a frame.
+struct RenderState<'a> {
+    /// Url of the page, for resolving links.
+    base: &'a url::Url,
+    settings: &'a settings::Settings,
+    theme: &'a theme::Theme,
+    /// The part of a link hint typed so far.
+    hint_typed: &'a str,
+    /// Lines without any strong direction of their own are right to left.
+    rtl: bool,
+    /// Digits in the last line number of a plain text document.
+    line_number_width: usize,
+    links: LinkEvents,
+    /// Text laid out so far this frame, for selecting.
+    texts: Vec<selection::Text>,
+}
+
+impl RenderState<'_> {
+    fn resolve(&self, url: &str) -> String {
+        self.base.join(url).map_or_else(|_| url.to_owned(), |url| url.to_string())
+    }
+
+    fn link_colour(&self, url: &str) -> egui::Color32 {
+        if visited::is_visited(&self.resolve(url)) {
+            self.theme.visited_link
+        } else if url.starts_with("http://") || url.starts_with("https://") {
+            self.theme.external_link
+        } else {
+            self.theme.gemini_link
+        }
+    }
+
+    /// Byte offset into the text of the line last laid out under the pointer, for finding links in it.
+    fn offset_at(&self, response: &egui::Response) -> Option<usize> {
+        let pointer = response.interact_pointer_pos().or(response.hover_pos())?;
+        self.texts.last()?.offset_at(pointer)
+    }
+
+    /// Follow or show `url` if it's the link under the pointer in `response`.
+    fn follow(&mut self, ui: &egui::Ui, response: &egui::Response, url: Option<&str>) {
+        let Some(url) = url else {
+            return;
+        };
+
+        ui.ctx().set_cursor_icon(egui::CursorIcon::PointingHand);
+        self.links.hovered = Some(self.resolve(url));
+        if response.middle_clicked() || (response.clicked() && ui.input(|i| i.modifiers.command)) {
+            self.links.followed = Some((url.into(), Target::NewTab));
+        } else if response.clicked() {
+            self.links.followed = Some((url.into(), Target::Current));
+        }
+    }
+
+    /// A line of plain text in monospace, with its urls as links and optionally its line number.
+    fn plain_label(&mut self, ui: &mut egui::Ui, line: usize, plain: &plaintext::Line, matches: &[(Range<usize>, bool)], colour: egui::Color32) -> egui::Response {
+        if plain.page_break {
+            ui.separator();
+        }
+
+        let settings = self.settings;
+        let font_id = settings.typography.preformatted_font();
+        let job = layout(ui, "", &plain.text, egui::TextFormat::simple(font_id.clone(), egui::Color32::PLACEHOLDER), matches);
+        let colours: Vec<egui::Color32> = plain.links.iter().map(|l| self.link_colour(&plain.text[l.clone()])).collect();
+        let job = restyle(job, 0, &plain.links, |i, format| egui::TextFormat {
+            color: colours[i],
+            underline: egui::Stroke::new(1.0, colours[i]),
+            ..format
+        });
+        let job = if settings.plain_text_line_numbers {
+            let number = format!("{:>width$} ", plain.number, width = self.line_number_width);
+            prepend(job, &number, egui::TextFormat::simple(font_id, ui.visuals().weak_text_color()))
+        } else {
+            job
+        };
+
+        let response = ui.scope(|ui| {
+            ui.style_mut().wrap = Some(settings.plain_text_wrap);
+            selection::label(ui, &mut self.texts, line, plain.text.len(), job, colour, false)
+        }).inner;
+
+        let url = self.offset_at(&response).and_then(|offset| plain.link_at(offset));
+        self.follow(ui, &response, url);
+        response
+    }
+
+    /// [`RenderState::label`] for text with links in it, `links` are byte ranges of the whole line's text, see [`GemLine::links`].
+    fn linked_label(&mut self, ui: &mut egui::Ui, at: impl Into<selection::Position>, text_len: usize, job: egui::text::LayoutJob, colour: egui::Color32, links: &[(Range<usize>, &str)]) -> egui::Response {
+        let response = self.label(ui, at, text_len, job, colour, false);
+        if !links.is_empty() {
+            let url = self.offset_at(&response).and_then(|offset| links.iter().find(|(range, _)| range.contains(&offset)).map(|(_, url)| *url));
+            self.follow(ui, &response, url);
+        }
+        response
+    }
+
+    /// [`selection::label`] for prose, which is reordered and aligned right if it needs to be, see [`bidi`].
+    fn label(&mut self, ui: &mut egui::Ui, at: impl Into<selection::Position>, text_len: usize, job: egui::text::LayoutJob, colour: egui::Color32, link: bool) -> egui::Response {
+        let text = &job.text[job.text.len() - text_len..];
+        let rtl = bidi::is_rtl(text, self.rtl);
+        if !bidi::needs_reordering(text, rtl) {
+            return selection::label(ui, &mut self.texts, at, text_len, job, colour, link);
+        }
+
+        let (job, order) = bidi::reorder(ui, job, ui.available_width(), rtl);
+        let align = if rtl { egui::Align::Max } else { egui::Align::Min };
+        ui.with_layout(egui::Layout::top_down(align), |ui| {
+            selection::label(ui, &mut self.texts, at, text_len, selection::Job { job, order: Some(order) }, colour, link)
+        }).inner
+    }
 }
 
+/// Where lines were last laid out, so lines outside the visible area can be skipped.
 #[derive(Debug, Default)]
-pub struct GemText(Vec<(u64, GemLine)>);
+struct Layout {
+    width: f32,
+    /// What the estimates were made with, and whether plain text was wrapped.
+    typography: Option<(settings::Typography, bool)>,
+    /// Height of each line including item spacing, measured or estimated.
+    heights: Vec<f32>,
+    /// Offset of the top of each line from the top of the document, one past the end for the total height.
+    offsets: Vec<f32>,
+    /// Number of links before each line, one past the end for the total.
+    links_before: Vec<usize>,
+    /// First line whose offset needs recalculating.
+    dirty: usize,
+}
+
+impl Layout {
+    fn update(&mut self, ui: &egui::Ui, lines: &[(u64, GemLine)], settings: &settings::Settings) {
+        let width = ui.available_width();
+        let typography = (settings.typography.clone(), settings.plain_text_wrap);
+
+        if self.heights.len() > lines.len() || self.width != width || self.typography.as_ref() != Some(&typography) {
+            // estimates depend on the width and text size, measured heights are refreshed as lines come into view
+            self.width = width;
+            self.typography = Some(typography);
+            self.heights = lines.iter().map(|(_, l)| l.estimate_height(ui, settings)).collect();
+            self.offsets = vec![0.0; lines.len() + 1];
+            self.links_before = std::iter::once(0)
+                .chain(lines.iter().scan(0, |n, (_, l)| {
+                    *n += l.links().len();
+                    Some(*n)
+                }))
+                .collect();
+            self.dirty = 0;
+        } else if self.heights.len() < lines.len() {
+            // lines of a page that's still arriving are added to the end, what's above stays where it is
+            let start = self.heights.len();
+            for (_, line) in &lines[start..] {
+                self.heights.push(line.estimate_height(ui, settings));
+                self.offsets.push(0.0);
+                let before = self.links_before.last().copied().unwrap_or_default();
+                self.links_before.push(before + line.links().len());
+            }
+            self.dirty = self.dirty.min(start);
+        }
+
+        for i in self.dirty..lines.len() {
+            self.offsets[i + 1] = self.offsets[i] + self.heights[i];
+        }
+        self.dirty = lines.len();
+    }
+
+    fn measured(&mut self, line: usize, height: f32) {
+        if self.heights[line] != height {
+            self.heights[line] = height;
+            self.dirty = self.dirty.min(line);
+        }
+    }
+}
+
+/// Parses gemtext as it arrives, a chunk at a time.
+///
+/// Lines are returned as soon as they are complete, so the result is the same
+/// however the input is split up.
+#[derive(Debug, Default)]
+pub struct Parser {
+    /// Start of a line whose newline hasn't arrived yet.
+    partial: Vec<u8>,
+    /// The block currently open, if any.
+    preformatted: Option<Preformatted>,
+}
+
+impl Parser {
+    /// Feed the next chunk of input, returning the lines it completed.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<(u64, GemLine)> {
+        let mut lines = Vec::new();
+        let mut chunk = chunk;
+
+        while let Some(i) = chunk.iter().position(|b| *b == b'\n') {
+            self.partial.extend_from_slice(&chunk[..i]);
+            chunk = &chunk[i + 1..];
+
+            let line = std::mem::take(&mut self.partial);
+            lines.extend(self.line(&String::from_utf8_lossy(&line)));
+        }
+        self.partial.extend_from_slice(chunk);
+
+        lines
+    }
+
+    /// End of input, returns the last line if it had no newline and any preformatted block left open.
+    pub fn finish(mut self) -> Vec<(u64, GemLine)> {
+        let mut lines = Vec::new();
+
+        let line = std::mem::take(&mut self.partial);
+        if !line.is_empty() {
+            lines.extend(self.line(&String::from_utf8_lossy(&line)));
+        }
+        // the rest of the document is preformatted, as if it was closed at the end
+        if self.preformatted.is_some() {
+            lines.extend(self.line("```"));
+        }
+
+        lines
+    }
+
+    fn line(&mut self, line: &str) -> Option<(u64, GemLine)> {
+        let id = LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+
+        // lines may end in CRLF as well as LF
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if let Some(pf) = &mut self.preformatted {
+            // anything after the closing toggle is ignored
+            if line.starts_with("```") {
+                if pf.contents.ends_with("\n") {
+                    pf.contents = pf.contents[..pf.contents.len()-1].into();
+                }
+                if ansi::has_escapes(&pf.contents) {
+                    (pf.contents, pf.ansi) = ansi::parse(&pf.contents);
+                }
+                return Some((id, GemLine::Preformatted(self.preformatted.take().expect("unreachable"))));
+            }
+            pf.contents += line;
+            pf.contents += "\n";
+            return None;
+        }
+
+        // whitespace in gemtext syntax is spaces and tabs only
+        let trim_start = |s: &str| s.trim_start_matches([' ', '\t']).to_owned();
+
+        let line = if let Some(alt) = line.strip_prefix("```") {
+            self.preformatted = Some(Preformatted {
+                alt: alt.into(),
+                contents: String::new(),
+                ansi: Vec::new(),
+            });
+            return None;
+        } else if let Some(line) = line.strip_prefix("###") {
+            GemLine::Heading(3, trim_start(line))
+        } else if let Some(line) = line.strip_prefix("##") {
+            GemLine::Heading(2, trim_start(line))
+        } else if let Some(line) = line.strip_prefix("#") {
+            GemLine::Heading(1, trim_start(line))
+        } else if let Some(link) = line.strip_prefix("=>").map(trim_start).filter(|l| !l.is_empty()) {
+            let (url, display) = link.split_once([' ', '\t']).unwrap_or((&link, ""));
+            let display = display.trim_matches([' ', '\t']);
+
+            GemLine::Link(url.into(), if display.is_empty() { url } else { display }.into())
+        } else if let Some(item) = line.strip_prefix("* ") {
+            GemLine::ListItem(item.into())
+        } else if let Some(quote) = line.strip_prefix(">") {
+            GemLine::Quote(trim_start(quote))
+        } else {
+            GemLine::Text(line.into())
+        };
+        Some((id, line))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GemText {
+    lines: Vec<(u64, GemLine)>,
+    /// From the `lang` parameter of the response, see [`GemText::with_lang`].
+    rtl: bool,
+    layout: RefCell<Layout>,
+    selection: RefCell<selection::Selection>,
+}
 
 static LAST_ID: AtomicU64 = AtomicU64::new(0);
 
 impl GemText {
+    /// Extra distance above and below the visible area to lay out lines in.
+    const OVERSCAN: f32 = 200.0;
+
     pub fn new(contents: &str) -> GemText {
-        let contents: String = contents.into();
-        
-        let mut lines = Vec::new();
+        let mut parser = Parser::default();
+        let mut lines = parser.push(contents.as_bytes());
+        lines.extend(parser.finish());
+
+        GemText {
+            lines,
+            rtl: false,
+            layout: Default::default(),
+            selection: Default::default(),
+        }
+    }
+
+    pub fn raw(contents: impl Into<String>) -> GemText {
+        GemText {
+            lines: vec![(LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel), GemLine::Text(contents.into()))],
+            rtl: false,
+            layout: Default::default(),
+            selection: Default::default(),
+        }
+    }
+
+    /// A markdown document, see [`markdown`].
+    pub fn markdown(contents: &str) -> GemText {
+        GemText {
+            lines: markdown::lines(contents).into_iter()
+                .map(|line| (LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel), line))
+                .collect(),
+            rtl: false,
+            layout: Default::default(),
+            selection: Default::default(),
+        }
+    }
+
+    /// A plain text document, each line shown as it is in monospace.
+    pub fn plain(contents: &str) -> GemText {
+        GemText {
+            lines: plaintext::lines(contents).into_iter()
+                .map(|line| (LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel), GemLine::Plain(line)))
+                .collect(),
+            rtl: false,
+            layout: Default::default(),
+            selection: Default::default(),
+        }
+    }
+
+    /// The source of a response, see [`source::lines`].
+    pub fn view_source(raw: &gemini::RawResponse) -> GemText {
+        GemText {
+            lines: source::lines(raw).into_iter()
+                .map(|line| (LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel), line))
+                .collect(),
+            rtl: false,
+            layout: Default::default(),
+            selection: Default::default(),
+        }
+    }
+
+    /// Shown in monospace with optional wrapping and line numbers, plain text or a page's source.
+    pub fn is_plain(&self) -> bool {
+        matches!(self.lines.first(), Some((_, GemLine::Plain(_) | GemLine::Source(..))))
+    }
+
+    /// Set the language of the document, e.g. `fa` or `en-GB`, from the `lang` parameter of its mimetype.
+    ///
+    /// Lines in a right to left language are still laid out left to right if
+    /// they start with a left to right word, the language is for lines with no letters.
+    pub fn with_lang(mut self, lang: &str) -> GemText {
+        self.rtl = bidi::is_rtl_language(lang);
+        self
+    }
 
-        let mut preformatted: Option<Preformatted> = None;
+    /// Add lines to the end of a document that's still arriving, see [`Parser::push`].
+    pub fn extend(&mut self, lines: Vec<(u64, GemLine)>) {
+        self.lines.extend(lines);
+    }
+
+    /// Identifies this document, line ids are never reused so the first one is unique to it.
+    pub fn id(&self) -> Option<u64> {
+        self.lines.first().map(|(id, _)| *id)
+    }
+
+    pub fn lines(&self) -> impl ExactSizeIterator<Item = &GemLine> {
+        self.lines.iter().map(|(_, l)| l)
+    }
+
+    /// Number of links in the document, including ones in the text of other lines.
+    pub fn link_count(&self) -> usize {
+        self.lines.iter().map(|(_, l)| l.links().len()).sum()
+    }
+
+    /// Url of the link at `index`, counting only links.
+    pub fn link(&self, index: usize) -> Option<&str> {
+        self.lines.iter()
+            .flat_map(|(_, l)| l.links().into_iter().map(|(_, url)| url))
+            .nth(index)
+    }
+
+    /// Search the text of every line, see [`find::find_all`].
+    pub fn find(&self, query: &str, case_sensitive: bool, whole_word: bool) -> Vec<find::Match> {
+        self.lines.iter()
+            .enumerate()
+            .flat_map(|(line, (_, l))| {
+                find::find_all(l.text(), query, case_sensitive, whole_word)
+                    .into_iter()
+                    .map(move |range| find::Match { line, range })
+            })
+            .collect()
+    }
+
+    /// Render the document found at `base` in `theme`, `hint_typed` is the part of a link hint typed so far.
+    ///
+    /// Only lines in or near the visible part of `ui` are laid out, the rest are
+    /// skipped over using their last known (or estimated) heights.
+    pub fn render(&self, ui: &mut egui::Ui, base: &url::Url, mut find: Option<&mut find::Find>, settings: &settings::Settings, theme: &theme::Theme, hint_typed: &str) -> LinkEvents {
+        ui.spacing_mut().item_spacing.y = settings.typography.paragraph_spacing;
+        // for anything not given a colour of its own, like ANSI text without one
+        ui.visuals_mut().override_text_color = Some(theme.text);
+
+        let mut layout = self.layout.borrow_mut();
+        layout.update(ui, &self.lines, settings);
+
+        let link_count = if settings.link_hints == hints::LinkHints::Off { 0 } else { layout.links_before[self.lines.len()] };
+
+        let origin = ui.cursor().top();
+        let visible = ui.clip_rect().expand2(egui::vec2(0.0, Self::OVERSCAN));
+        let offsets = &layout.offsets[..self.lines.len()];
+        let first = offsets.partition_point(|o| origin + o <= visible.top()).saturating_sub(1);
+        let end = offsets.partition_point(|o| origin + o <= visible.bottom()).max(first);
+
+        if let Some(target) = find.as_ref().and_then(|f| f.scroll_target()) {
+            if !(first..end).contains(&target) {
+                // bring it close enough to be laid out, it is scrolled to exactly next frame
+                let top = origin + layout.offsets[target];
+                let bottom = origin + layout.offsets[target + 1];
+                ui.scroll_to_rect(egui::Rect::from_x_y_ranges(ui.max_rect().x_range(), top..=bottom), Some(egui::Align::Center));
+            }
+        }
+
+        ui.add_space(layout.offsets[first]);
 
-        for line in contents.split("\n") {
-            let id = LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel);
+        let mut link_index = layout.links_before[first];
 
-            if let Some(pf) = &mut preformatted {
-                if line.starts_with("```") {
-                    if pf.contents.ends_with("\n") {
-                        pf.contents = pf.contents[..pf.contents.len()-1].into();
+        let mut state = RenderState {
+            base,
+            settings,
+            theme,
+            hint_typed,
+            rtl: self.rtl,
+            line_number_width: match self.lines.last() {
+                Some((_, GemLine::Plain(line) | GemLine::Source(line, _))) => line.number.to_string().len(),
+                _ => 0,
+            },
+            links: Default::default(),
+            texts: Vec::new(),
+        };
+
+        for (i, (id, line)) in self.lines.iter().enumerate().take(end).skip(first) {
+            let matches = find.as_ref().map(|f| f.matches_on(i)).unwrap_or_default();
+            let links = if link_count > 0 { line.links() } else { Vec::new() };
+            let hints: Vec<String> = (link_index..link_index + links.len()).map(|n| hints::hint(settings.link_hints, n, link_count)).collect();
+            link_index += links.len();
+            // a link line's hint goes before its label, links in other text have theirs painted over them
+            let hint = match line {
+                GemLine::Link(..) => hints.first().cloned().unwrap_or_default(),
+                _ => String::new(),
+            };
+
+            let before = ui.cursor().top();
+            let response = ui.push_id(id, |ui| {
+                line.render(ui, &mut state, i, &matches, &hint)
+            }).inner;
+            layout.measured(i, ui.cursor().top() - before);
+
+            if hint.is_empty() {
+                for ((range, _), hint) in links.iter().zip(&hints) {
+                    let pos = state.texts.iter().rev().filter(|t| t.line() == i).find_map(|t| t.pos_of(range.start));
+                    if let Some(pos) = pos {
+                        paint_hint(ui, pos, hint, hint_typed);
                     }
-                    lines.push((id, GemLine::Preformatted(preformatted.take().expect("unreachable"))));
-                } else {
-                    pf.contents += line;
-                    pf.contents += "\n";
                 }
+            }
+
+            if let Some(find) = &mut find {
+                if find.take_scroll(i) {
+                    response.scroll_to_me(Some(egui::Align::Center));
+                }
+            }
+        }
+
+        // offsets after `end` haven't been updated yet, but the distance to the bottom hasn't changed
+        ui.add_space(layout.offsets[self.lines.len()] - layout.offsets[end]);
+
+        self.update_selection(ui, &state.texts);
+        self.context_menus(&mut state);
+
+        state.links
+    }
+
+    fn update_selection(&self, ui: &egui::Ui, texts: &[selection::Text]) {
+        let mut selection = self.selection.borrow_mut();
+        selection.update(ui, texts);
+
+        if !ui.ctx().wants_keyboard_input() {
+            let select_all = egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::A);
+            if ui.input_mut(|i| i.consume_shortcut(&select_all)) {
+                if let Some((_, last)) = self.lines.last() {
+                    selection.select_all(self.lines.len() - 1, last.text().len());
+                }
+            }
+
+            // shift copies the gemtext source instead
+            let copy = ui.input(|i| i.events.contains(&egui::Event::Copy).then_some(i.modifiers.shift));
+            if let Some(source) = copy.filter(|_| !selection.is_empty()) {
+                ui.ctx().copy_text(self.selected_text(&selection, source));
+            }
+        }
+
+        selection.paint(ui, texts);
+    }
+
+    /// Right click menus for links, and for copying the selection.
+    fn context_menus(&self, state: &mut RenderState) {
+        let selection = self.selection.borrow();
+        let responses: Vec<(usize, egui::Response, Option<usize>)> = state.texts.iter()
+            .map(|t| {
+                let response = t.response().clone();
+                let offset = response.interact_pointer_pos().and_then(|pos| t.offset_at(pos));
+                (t.line(), response, offset)
+            })
+            .collect();
+
+        for (line, response, offset) in responses {
+            let text = self.lines[line].1.text();
+            let links = self.lines[line].1.links();
+
+            // the menu stays open after the click, so the link it was opened on is remembered
+            let id = response.id.with("context_link");
+            if response.secondary_clicked() {
+                let link = offset.and_then(|offset| links.iter().position(|(range, _)| range.contains(&offset)));
+                response.ctx.data_mut(|d| d.insert_temp(id, link));
+            }
+            let link = response.ctx.data(|d| d.get_temp::<Option<usize>>(id))
+                .flatten()
+                .and_then(|i| links.get(i))
+                .map(|(range, url)| (url.to_string(), text[range.clone()].to_owned()));
+            if link.is_none() && selection.is_empty() {
                 continue;
             }
 
-            let line = if line.starts_with("```") {
-                preformatted = Some(Preformatted {
-                    alt: line[3..].into(),
-                    contents: String::new(),
-                });
+            response.context_menu(|ui| {
+                if let Some((url, display)) = &link {
+                    if ui.button("Open in new tab").clicked() {
+                        state.links.followed = Some((url.clone(), Target::NewTab));
+                        ui.close_menu();
+                    }
+                    if ui.button("Open in background tab").clicked() {
+                        state.links.followed = Some((url.clone(), Target::BackgroundTab));
+                        ui.close_menu();
+                    }
+                    if ui.button("Save link as\u{2026}").clicked() {
+                        state.links.followed = Some((url.clone(), Target::Download));
+                        ui.close_menu();
+                    }
+                    ui.separator();
+                    if ui.button("Copy link URL").clicked() {
+                        ui.ctx().copy_text(state.resolve(url));
+                        ui.close_menu();
+                    }
+                    if ui.button("Copy link text").clicked() {
+                        ui.ctx().copy_text(display.clone());
+                        ui.close_menu();
+                    }
+                    if ui.button("Bookmark link").clicked() {
+                        bookmarks::add(&state.resolve(url), display);
+                        ui.close_menu();
+                    }
+                }
+                if !selection.is_empty() {
+                    if link.is_some() {
+                        ui.separator();
+                    }
+                    if ui.button("Copy").clicked() {
+                        ui.ctx().copy_text(self.selected_text(&selection, false));
+                        ui.close_menu();
+                    }
+                    if ui.button("Copy as gemtext").clicked() {
+                        ui.ctx().copy_text(self.selected_text(&selection, true));
+                        ui.close_menu();
+                    }
+                }
+            });
+        }
+    }
+
+    /// The selected text, one line per line of the document, or the gemtext it came from if `source` is set.
+    fn selected_text(&self, selection: &selection::Selection, source: bool) -> String {
+        let (start, end) = selection.range();
+
+        self.lines.iter()
+            .enumerate()
+            .take(end.line + 1)
+            .skip(start.line)
+            .filter_map(|(i, (_, line))| {
+                let range = selection.on_line(i, line.text().len())?;
+                Some(if source { line.source(range) } else { line.text()[range].to_owned() })
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// Lay out `prefix` followed by `text` in `format`, with the given byte ranges of `text` highlighted as find matches.
+///
+/// The colour should be left as a placeholder, it is filled in by the widget so links and labels keep their own colours.
+fn layout(ui: &egui::Ui, prefix: &str, text: &str, format: egui::TextFormat, matches: &[(Range<usize>, bool)]) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    job.append(prefix, 0.0, format.clone());
+    job.append(text, 0.0, format);
+
+    mark_matches(ui, job, prefix.len(), matches)
+}
+
+/// Lay out markdown text after `prefix`, with its styles and links, marking `matches`.
+fn inline_layout(ui: &egui::Ui, state: &RenderState, prefix: &str, inline: &markdown::Inline, format: egui::TextFormat, matches: &[(Range<usize>, bool)]) -> egui::text::LayoutJob {
+    let mut job = egui::text::LayoutJob::default();
+    job.append(prefix, 0.0, format.clone());
+
+    for (range, style) in &inline.spans {
+        let mut format = format.clone();
+        if style.code {
+            format.font_id = egui::FontId::monospace(format.font_id.size);
+            format.background = ui.visuals().code_bg_color;
+        }
+        if style.strong {
+            format.color = state.theme.heading(1);
+        }
+        format.italics = style.emphasis;
+        if style.strikethrough {
+            format.strikethrough = egui::Stroke::new(1.0, state.theme.text);
+        }
+        if let Some(url) = &style.link {
+            format.color = state.link_colour(url);
+            format.underline = egui::Stroke::new(1.0, format.color);
+        }
+        job.append(&inline.text[range.clone()], 0.0, format);
+    }
+
+    mark_matches(ui, job, prefix.len(), matches)
+}
+
+/// Put a keyboard hint in front of a link's label, emphasised if it matches what was typed so far.
+fn with_hint(ui: &egui::Ui, job: egui::text::LayoutJob, hint: &str, typed: &str) -> egui::text::LayoutJob {
+    if hint.is_empty() {
+        return job;
+    }
+
+    let color = if !typed.is_empty() && hint.starts_with(typed) {
+        ui.visuals().strong_text_color()
+    } else {
+        ui.visuals().weak_text_color()
+    };
+
+    prepend(job, &format!("[{hint}] "), egui::TextFormat::simple(egui::TextStyle::Monospace.resolve(ui.style()), color))
+}
+
+/// Paint a keyboard hint over a link in running text, whose first char is at `pos`.
+fn paint_hint(ui: &egui::Ui, pos: egui::Pos2, hint: &str, typed: &str) {
+    let color = if !typed.is_empty() && hint.starts_with(typed) {
+        ui.visuals().strong_text_color()
+    } else {
+        ui.visuals().weak_text_color()
+    };
+
+    let galley = ui.painter().layout_no_wrap(hint.into(), egui::TextStyle::Monospace.resolve(ui.style()), color);
+    let rect = egui::Rect::from_min_size(pos, galley.size()).expand2(egui::vec2(2.0, 0.0));
+    ui.painter().rect(rect, 2.0, ui.visuals().extreme_bg_color, ui.visuals().widgets.noninteractive.bg_stroke);
+    ui.painter().galley(pos, galley, color);
+}
+
+/// Put `text` in `format` in front of `job`.
+fn prepend(job: egui::text::LayoutJob, text: &str, format: egui::TextFormat) -> egui::text::LayoutJob {
+    let mut out = egui::text::LayoutJob::default();
+    out.append(text, 0.0, format);
+
+    let offset = out.text.len();
+    out.text += &job.text;
+    out.sections.extend(job.sections.into_iter().map(|mut section| {
+        section.byte_range = section.byte_range.start + offset..section.byte_range.end + offset;
+        section
+    }));
+
+    out
+}
+
+/// Give the parts of `job` covered by `matches` a highlighted background.
+///
+/// Match ranges are relative to `offset` bytes into the job's text.
+fn mark_matches(ui: &egui::Ui, job: egui::text::LayoutJob, offset: usize, matches: &[(Range<usize>, bool)]) -> egui::text::LayoutJob {
+    let selection = ui.visuals().selection.bg_fill;
+    let ranges: Vec<Range<usize>> = matches.iter().map(|(range, _)| range.clone()).collect();
+
+    restyle(job, offset, &ranges, |i, format| egui::TextFormat {
+        background: if matches[i].1 { selection } else { selection.gamma_multiply(0.4) },
+        ..format
+    })
+}
+
+/// Change the format of the parts of `job` covered by `ranges`, which mustn't overlap.
+///
+/// Ranges are relative to `offset` bytes into the job's text, `style` is given
+/// the index of the range and the format being changed.
+fn restyle(mut job: egui::text::LayoutJob, offset: usize, ranges: &[Range<usize>], style: impl Fn(usize, egui::TextFormat) -> egui::TextFormat) -> egui::text::LayoutJob {
+    if ranges.is_empty() {
+        return job;
+    }
+
+    let mut sections = Vec::with_capacity(job.sections.len() + ranges.len() * 2);
+    for section in job.sections.drain(..) {
+        let mut start = section.byte_range.start;
+        let end = section.byte_range.end;
+
+        for (i, range) in ranges.iter().enumerate() {
+            let range = range.start + offset..range.end + offset;
+            if range.end <= start || range.start >= end {
                 continue;
-            } else if line.starts_with("###") {
-                let line = line[3..].trim_start();
-                GemLine::Heading(3, line.into())
-            } else if line.starts_with("##") {
-                let line = line[2..].trim_start();
-                GemLine::Heading(2, line.into())
-            } else if line.starts_with("#") {
-                let line = line[1..].trim_start();
-                GemLine::Heading(1, line.into())
-            } else if line.starts_with("=>") {
-                let line = line[2..].trim_start();
-                let i = line.split_whitespace().next().map(|s| s.len()).unwrap_or_default();
-                let url = line[..i].trim_end();
-                let display = line[i..].trim_start();
-
-                GemLine::Link(url.into(), display.into())
-            } else {
-                GemLine::Text(line.into())
-            };
-            lines.push((id, line));
+            }
+            if range.start > start {
+                sections.push(egui::text::LayoutSection {
+                    leading_space: if start == section.byte_range.start { section.leading_space } else { 0.0 },
+                    byte_range: start..range.start,
+                    format: section.format.clone(),
+                });
+                start = range.start;
+            }
+            let marked_end = range.end.min(end);
+            sections.push(egui::text::LayoutSection {
+                leading_space: if start == section.byte_range.start { section.leading_space } else { 0.0 },
+                byte_range: start..marked_end,
+                format: style(i, section.format.clone()),
+            });
+            start = marked_end;
         }
 
-        GemText(lines)
+        if start < end {
+            sections.push(egui::text::LayoutSection {
+                leading_space: if start == section.byte_range.start { section.leading_space } else { 0.0 },
+                byte_range: start..end,
+                format: section.format,
+            });
+        }
     }
+    job.sections = sections;
 
-    pub fn raw(contents: impl Into<String>) -> GemText {
-        GemText(vec![(LAST_ID.fetch_add(1, std::sync::atomic::Ordering::AcqRel), GemLine::Text(contents.into()))])
-    }
-
-    pub fn render(&self, ui: &mut egui::Ui, new_url: &mut Option<String>) {
-        for (id, line) in self.0.iter() {
-            ui.push_id(id, |ui| {
-                match line {
-                    GemLine::Text(text) => {
-                        ui.label(text);
-                    },
-                    GemLine::Heading(n, text) => {
-                        ui.label(egui::RichText::new(text).size(12.0 + 12.0 / *n as f32));
-                    },
-                    GemLine::Link(url, display) => {
-                        if url.starts_with(&"http://") || url.starts_with(&"https://") {
-                            ui.hyperlink_to(format!("\u{1F310} {display}"), url);
-                        } else {
-                            if ui.link(format!("\u{1F680} {display}")).clicked() {
-                                *new_url = Some(url.into());
-                            }
-                        }
-                    }
-                    GemLine::Preformatted(Preformatted {
-                        // alt,
-                        contents,
-                        ..
-                    }) => {
-                        egui::ScrollArea::horizontal().show(ui, |ui| {
-                            ui.horizontal(|ui| {
-                                ui.label(egui::RichText::new(contents).monospace());
-                            });
-                        });
-                    },
-                };
+    job
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    fn page(lines: usize) -> String {
+        (0..lines).map(|i| match i % 10 {
+            0 => format!("## Section {i}"),
+            3 => format!("=> gemini://example.org/{i} Link number {i}"),
+            _ => format!("Line {i} of a rather long mailing list archive, with enough text that it will wrap on narrow windows."),
+        }).collect::<Vec<_>>().join("\n")
+    }
+
+    fn without_ids(lines: Vec<(u64, GemLine)>) -> Vec<GemLine> {
+        lines.into_iter().map(|(_, l)| l).collect()
+    }
+
+    fn text(s: &str) -> GemLine {
+        GemLine::Text(s.into())
+    }
+
+    fn link(url: &str, display: &str) -> GemLine {
+        GemLine::Link(url.into(), display.into())
+    }
+
+    fn pre(alt: &str, contents: &str) -> GemLine {
+        GemLine::Preformatted(Preformatted { alt: alt.into(), contents: contents.into(), ansi: Vec::new() })
+    }
+
+    /// Examples from the gemtext specification, plus the edge cases around them.
+    #[test]
+    fn spec_conformance() {
+        let cases: &[(&str, &[GemLine])] = &[
+            // line endings
+            ("", &[]),
+            ("\n", &[text("")]),
+            ("one\ntwo", &[text("one"), text("two")]),
+            ("one\r\ntwo\r\n", &[text("one"), text("two")]),
+            ("# Title\r\n=> gemini://example.org/\r\n", &[GemLine::Heading(1, "Title".into()), link("gemini://example.org/", "gemini://example.org/")]),
+            ("trailing\r", &[text("trailing")]),
+            // text is shown as is
+            ("  indented\ttext ", &[text("  indented\ttext ")]),
+            ("Just a line\n\nAnother", &[text("Just a line"), text(""), text("Another")]),
+            // links
+            ("=> gemini://example.org/", &[link("gemini://example.org/", "gemini://example.org/")]),
+            ("=> gemini://example.org/ An example link", &[link("gemini://example.org/", "An example link")]),
+            ("=> gemini://example.org/foo\tAnother example link at the same host", &[link("gemini://example.org/foo", "Another example link at the same host")]),
+            ("=>gemini://example.org/bar Yet another example link at the same host", &[link("gemini://example.org/bar", "Yet another example link at the same host")]),
+            ("=> foo/bar/baz.txt\tA relative link", &[link("foo/bar/baz.txt", "A relative link")]),
+            ("=> \tgopher://example.org:70/1 A gopher link", &[link("gopher://example.org:70/1", "A gopher link")]),
+            ("=> url \t  spaced name \t", &[link("url", "spaced name")]),
+            ("=> url name with  inner   spaces", &[link("url", "name with  inner   spaces")]),
+            ("=> url\u{a0}nbsp", &[link("url\u{a0}nbsp", "url\u{a0}nbsp")]),
+            ("=>", &[text("=>")]),
+            ("=> \t ", &[text("=> \t ")]),
+            ("=>\r\n", &[text("=>")]),
+            // headings
+            ("# Heading", &[GemLine::Heading(1, "Heading".into())]),
+            ("#Heading", &[GemLine::Heading(1, "Heading".into())]),
+            ("## Sub-heading", &[GemLine::Heading(2, "Sub-heading".into())]),
+            ("##\tTabbed", &[GemLine::Heading(2, "Tabbed".into())]),
+            ("### Sub-sub-heading", &[GemLine::Heading(3, "Sub-sub-heading".into())]),
+            ("#### Too deep", &[GemLine::Heading(3, "# Too deep".into())]),
+            ("#", &[GemLine::Heading(1, "".into())]),
+            // lists and quotes
+            ("* Mercury\n* Venus", &[GemLine::ListItem("Mercury".into()), GemLine::ListItem("Venus".into())]),
+            ("*Not a list item", &[text("*Not a list item")]),
+            ("**bold**", &[text("**bold**")]),
+            ("> A quote", &[GemLine::Quote("A quote".into())]),
+            (">No space", &[GemLine::Quote("No space".into())]),
+            (">", &[GemLine::Quote("".into())]),
+            // preformatted text
+            ("```\ncode\n```", &[pre("", "code")]),
+            ("```rust example\nfn main() {}\n```", &[pre("rust example", "fn main() {}")]),
+            ("```alt\r\ncode\r\n```\r\n", &[pre("alt", "code")]),
+            ("```\n```", &[pre("", "")]),
+            ("```\none\n\ntwo\n```", &[pre("", "one\n\ntwo")]),
+            ("```\n# not a heading\n=> not a link\n* not an item\n```", &[pre("", "# not a heading\n=> not a link\n* not an item")]),
+            ("```\n  keep   spacing  \n```", &[pre("", "  keep   spacing  ")]),
+            ("```open\ncode\n```text after the closing toggle is ignored\nafter", &[pre("open", "code"), text("after")]),
+            ("```\nnever closed", &[pre("", "never closed")]),
+            ("```\nnever closed\n", &[pre("", "never closed")]),
+            (" ```not a toggle", &[text(" ```not a toggle")]),
+        ];
+
+        for (source, expected) in cases {
+            assert_eq!(without_ids(GemText::new(source).lines), *expected, "parsing {source:?}");
+        }
+    }
+
+    #[test]
+    fn parser_matches_batch_at_every_split() {
+        let doc = "# Title\n\nSome text, ünïcödé included 🚀\n=> gemini://example.org/ Example\n```sh\n\x1b[31mred\x1b[0m\n$ ls\n```\r\n## End\r\n* item\n> quote";
+        let batch = without_ids(GemText::new(doc).lines);
+
+        for i in 0..=doc.len() {
+            let mut parser = Parser::default();
+            let mut lines = parser.push(&doc.as_bytes()[..i]);
+            lines.extend(parser.push(&doc.as_bytes()[i..]));
+            lines.extend(parser.finish());
+            assert_eq!(without_ids(lines), batch, "split at byte {i}");
+        }
+
+        let mut parser = Parser::default();
+        let mut lines: Vec<_> = doc.as_bytes().iter().flat_map(|b| parser.push(&[*b])).collect();
+        lines.extend(parser.finish());
+        assert_eq!(without_ids(lines), batch, "one byte at a time");
+    }
+
+    #[test]
+    fn drag_selects_and_copies_across_lines() {
+        let doc = GemText::new("# Heading\nFirst line\n=> gemini://example.org/ A link\n* Last line");
+        let ctx = egui::Context::default();
+        let settings = settings::Settings::default();
+        let base = url::Url::parse("gemini://example.org/").unwrap();
+
+        let frame = |events: Vec<egui::Event>, modifiers: egui::Modifiers| {
+            ctx.run(egui::RawInput {
+                screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(1024.0, 768.0))),
+                events,
+                modifiers,
+                ..Default::default()
+            }, |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    doc.render(ui, &base, None, &settings, &theme::Theme::dark(), "");
+                });
+            }).platform_output.copied_text
+        };
+        let button = |pos, pressed| egui::Event::PointerButton { pos, button: egui::PointerButton::Primary, pressed, modifiers: Default::default() };
+
+        // from the start of the heading to past the end of the page
+        let (start, end) = (egui::pos2(9.0, 12.0), egui::pos2(600.0, 700.0));
+        frame(vec![], Default::default());
+        frame(vec![egui::Event::PointerMoved(start), button(start, true)], Default::default());
+        frame(vec![egui::Event::PointerMoved(end)], Default::default());
+        frame(vec![egui::Event::PointerMoved(end), button(end, false)], Default::default());
+
+        assert_eq!(frame(vec![egui::Event::Copy], Default::default()), "Heading\nFirst line\nA link\nLast line");
+        assert_eq!(frame(vec![egui::Event::Copy], egui::Modifiers::SHIFT), "# Heading\nFirst line\n=> gemini://example.org/ A link\n* Last line");
+    }
+
+    /// Average frame time rendering `doc` scrolled to `offset`, after the first frame.
+    fn frame_time(doc: &GemText, offset: f32) -> Duration {
+        let ctx = egui::Context::default();
+        let input = || egui::RawInput {
+            screen_rect: Some(egui::Rect::from_min_size(egui::Pos2::ZERO, egui::vec2(1024.0, 768.0))),
+            ..Default::default()
+        };
+        let settings = settings::Settings::default();
+        let base = url::Url::parse("gemini://example.org/").unwrap();
+
+        let frame = || {
+            let _ = ctx.run(input(), |ctx| {
+                egui::CentralPanel::default().show(ctx, |ui| {
+                    egui::ScrollArea::vertical().vertical_scroll_offset(offset).show(ui, |ui| {
+                        doc.render(ui, &base, None, &settings, &theme::Theme::dark(), "");
+                    });
+                });
             });
+        };
+
+        frame();
+
+        const FRAMES: u32 = 20;
+        let start = Instant::now();
+        for _ in 0..FRAMES {
+            frame();
         }
+        start.elapsed() / FRAMES
+    }
+
+    /// Frame time on a 100k line page should stay close to that on a small page.
+    ///
+    /// Run with `cargo test --release -- --ignored bench_render`.
+    #[test]
+    #[ignore]
+    fn bench_render_100k_lines() {
+        let small = GemText::new(&page(1_000));
+        let large = GemText::new(&page(100_000));
+
+        let small_time = frame_time(&small, 0.0);
+        let large_top = frame_time(&large, 0.0);
+        let large_middle = frame_time(&large, 1_000_000.0);
+
+        println!("1k lines: {small_time:?}, 100k lines (top): {large_top:?}, 100k lines (middle): {large_middle:?}");
+
+        assert!(large_top < small_time * 2 + Duration::from_micros(500));
+        assert!(large_middle < small_time * 2 + Duration::from_micros(500));
     }
 }
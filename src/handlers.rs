@@ -0,0 +1,201 @@
+//! Other programs that open links and files Vostok can't, by URL scheme or mimetype.
+
+use std::{collections::BTreeSet, ffi::OsStr, io, process::Command, sync::{Mutex, MutexGuard}};
+
+pub const STORAGE_KEY: &str = "handlers";
+
+/// A command for links with a scheme, like `mailto:`, or responses with a mimetype, like `audio/*`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Handler {
+    /// A scheme ending in a colon, a mimetype, or a mimetype ending in `/*` for all of a type.
+    pub pattern: String,
+    /// Program and arguments, `{}` is replaced with the url or file, which is added to the end if there's no `{}`.
+    pub command: String,
+}
+
+impl Handler {
+    fn matches_scheme(&self, scheme: &str) -> bool {
+        self.pattern.trim().strip_suffix(':').is_some_and(|s| s.eq_ignore_ascii_case(scheme))
+    }
+
+    fn matches_mimetype(&self, mimetype: &str) -> bool {
+        let pattern = self.pattern.trim();
+        match pattern.strip_suffix("/*") {
+            Some(kind) => mimetype.split('/').next().is_some_and(|k| k.eq_ignore_ascii_case(kind)),
+            None => pattern.eq_ignore_ascii_case(mimetype),
+        }
+    }
+}
+
+/// A program to run, waiting for the reader to agree if it's the first time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Launch {
+    /// The scheme ending in a colon or the mimetype being opened, like `mailto:` or `audio/ogg`.
+    pub kind: String,
+    /// The handler's command, `None` for the desktop's default application.
+    pub command: Option<String>,
+    /// Url or path of a downloaded file.
+    pub argument: String,
+}
+
+impl Launch {
+    /// How the command is shown.
+    pub fn name(&self) -> &str {
+        self.command.as_deref().unwrap_or("the default application")
+    }
+
+    /// What's remembered once it's been agreed to, agreeing to a program for one kind of link or file doesn't agree to it for others.
+    fn key(&self) -> String {
+        format!("{} \u{2192} {}", self.kind, self.name())
+    }
+
+    pub fn is_confirmed(&self) -> bool {
+        confirmed().contains(&self.key())
+    }
+
+    /// Start the program, it's only remembered as agreed to once it has started.
+    pub fn run(&self) -> io::Result<()> {
+        self.start()?;
+        confirmed().insert(self.key());
+        Ok(())
+    }
+
+    fn start(&self) -> io::Result<()> {
+        let Some(command) = &self.command else {
+            return open(self.argument.as_ref());
+        };
+
+        let mut parts: Vec<String> = command.split_whitespace().map(Into::into).collect();
+        if parts.iter().any(|p| p.contains("{}")) {
+            for part in &mut parts {
+                *part = part.replace("{}", &self.argument);
+            }
+        } else {
+            parts.push(self.argument.clone());
+        }
+
+        let (program, args) = parts.split_first().ok_or(io::ErrorKind::InvalidInput)?;
+        Command::new(program).args(args).spawn().map(|_| ())
+    }
+}
+
+/// The handlers from the settings, which requests can't get to themselves.
+static HANDLERS: Mutex<Vec<Handler>> = Mutex::new(Vec::new());
+
+/// Commands the reader has agreed to run.
+static CONFIRMED: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+
+fn handlers() -> MutexGuard<'static, Vec<Handler>> {
+    HANDLERS.lock().expect("handlers lock poisoned")
+}
+
+fn confirmed() -> MutexGuard<'static, BTreeSet<String>> {
+    CONFIRMED.lock().expect("handlers lock poisoned")
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) {
+    if let Some(loaded) = storage.and_then(|storage| eframe::get_value::<BTreeSet<String>>(storage, STORAGE_KEY)) {
+        *confirmed() = loaded;
+    }
+}
+
+pub fn save(storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, STORAGE_KEY, &*confirmed());
+}
+
+pub fn set(configured: &[Handler]) {
+    let mut handlers = handlers();
+    if *handlers != configured {
+        *handlers = configured.to_vec();
+    }
+}
+
+/// What to open `url` with, the default application if there's no handler for its scheme.
+pub fn for_url(url: &url::Url) -> Launch {
+    Launch {
+        kind: format!("{}:", url.scheme()),
+        command: handlers().iter().find(|h| h.matches_scheme(url.scheme())).map(|h| h.command.clone()),
+        argument: url.to_string(),
+    }
+}
+
+/// What to open responses of `mimetype` with, if there's a handler for it, the file still has to be given.
+pub fn for_mimetype(mimetype: &str) -> Option<Launch> {
+    let mimetype = mimetype.split(';').next().unwrap_or_default().trim();
+    handlers().iter().find(|h| h.matches_mimetype(mimetype)).map(|h| Launch {
+        kind: mimetype.to_ascii_lowercase(),
+        command: Some(h.command.clone()),
+        argument: String::new(),
+    })
+}
+
+/// Open `target`, a path or url, with the desktop's default application for it.
+pub fn open(target: &OsStr) -> io::Result<()> {
+    // passed straight to the program, never through a shell which could read it as more commands
+    let mut command = if cfg!(target_os = "windows") {
+        Command::new("explorer")
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+    command.arg(target).spawn().map(|_| ())
+}
+
+/// Table of handlers for the settings window.
+pub fn ui(ui: &mut egui::Ui, handlers: &mut Vec<Handler>) {
+    ui.weak("Links with other schemes open in the default application, files Vostok can't show are downloaded.");
+
+    let mut remove = None;
+    egui::Grid::new("handlers").num_columns(3).show(ui, |ui| {
+        for (i, handler) in handlers.iter_mut().enumerate() {
+            ui.add(egui::TextEdit::singleline(&mut handler.pattern).hint_text("mailto: or audio/*").desired_width(120.0));
+            ui.add(egui::TextEdit::singleline(&mut handler.command).hint_text("program --option {}").desired_width(200.0));
+            if ui.button("Remove").clicked() {
+                remove = Some(i);
+            }
+            ui.end_row();
+        }
+    });
+
+    if let Some(i) = remove {
+        handlers.remove(i);
+    }
+    if ui.button("Add handler").clicked() {
+        handlers.push(Handler::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handlers_match_schemes_and_mimetypes() {
+        let mail = Handler { pattern: "mailto:".into(), command: "thunderbird -compose {}".into() };
+        let audio = Handler { pattern: "audio/*".into(), command: "mpv".into() };
+        let pdf = Handler { pattern: "application/pdf".into(), command: "zathura".into() };
+
+        assert!(mail.matches_scheme("MAILTO"));
+        assert!(!audio.matches_scheme("audio"));
+        assert!(audio.matches_mimetype("audio/ogg"));
+        assert!(!audio.matches_mimetype("video/ogg"));
+        assert!(pdf.matches_mimetype("application/pdf"));
+        assert!(!pdf.matches_scheme("application"));
+    }
+
+    #[test]
+    fn confirmations_are_for_one_kind() {
+        let launch = |kind: &str, command: Option<&str>| Launch { kind: kind.into(), command: command.map(Into::into), argument: "x".into() };
+        assert_eq!(launch("mailto:", None).key(), "mailto: \u{2192} the default application");
+        assert_ne!(launch("mailto:", None).key(), launch("gopher:", None).key());
+        assert_ne!(launch("audio/ogg", Some("mpv")).key(), launch("video/mp4", Some("mpv")).key());
+    }
+
+    #[test]
+    fn programs_that_fail_to_start_are_not_confirmed() {
+        let launch = Launch { kind: "test:".into(), command: Some("vostok-no-such-program {}".into()), argument: "x".into() };
+        assert!(launch.run().is_err());
+        assert!(!launch.is_confirmed());
+    }
+}
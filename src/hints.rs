@@ -0,0 +1,122 @@
+/// How links are labelled for keyboard navigation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, serde::Serialize, serde::Deserialize)]
+pub enum LinkHints {
+    #[default]
+    Off,
+    /// Number links from 1, type the number then Enter.
+    Numbers,
+    /// Label links with short letter sequences from the home row.
+    Letters,
+}
+
+/// Keys used for letter hints, most comfortable first.
+const ALPHABET: &[u8] = b"asdfghjklqwertyuiopzxcvbnm";
+
+/// The hint shown for the link at `index`, out of `count` links on the page.
+pub fn hint(mode: LinkHints, index: usize, count: usize) -> String {
+    match mode {
+        LinkHints::Off => String::new(),
+        LinkHints::Numbers => (index + 1).to_string(),
+        LinkHints::Letters => {
+            // all hints have the same length, so none is a prefix of another
+            let mut len = 1;
+            while ALPHABET.len().pow(len) < count {
+                len += 1;
+            }
+
+            let mut index = index;
+            let mut hint = vec![0; len as usize];
+            for c in hint.iter_mut().rev() {
+                *c = ALPHABET[index % ALPHABET.len()];
+                index /= ALPHABET.len();
+            }
+            String::from_utf8(hint).expect("hints are ascii")
+        },
+    }
+}
+
+/// A link chosen by typing its hint.
+pub struct Followed {
+    pub index: usize,
+    pub new_tab: bool,
+}
+
+/// Keys typed so far towards a hint.
+#[derive(Debug, Default)]
+pub struct HintInput {
+    typed: String,
+}
+
+impl HintInput {
+    pub fn typed(&self) -> &str {
+        &self.typed
+    }
+
+    /// Consume hint keys typed this frame, returning the link to follow once a hint is complete.
+    ///
+    /// Holding shift on the final key (or Enter) opens the link in a new tab.
+    pub fn update(&mut self, ctx: &egui::Context, mode: LinkHints, count: usize) -> Option<Followed> {
+        if mode == LinkHints::Off || count == 0 || ctx.wants_keyboard_input() {
+            self.typed.clear();
+            return None;
+        }
+
+        let events = ctx.input(|i| i.events.clone());
+
+        for event in events {
+            let egui::Event::Key { key, physical_key, pressed: true, modifiers, .. } = event else { continue; };
+
+            if modifiers.ctrl || modifiers.alt || modifiers.command {
+                continue;
+            }
+
+            // shifted digits are symbols on most layouts, so prefer where the key is
+            let key = physical_key.unwrap_or(key);
+            let new_tab = modifiers.shift;
+
+            match key {
+                egui::Key::Escape => self.typed.clear(),
+                egui::Key::Backspace => {
+                    self.typed.pop();
+                },
+                egui::Key::Enter if mode == LinkHints::Numbers => {
+                    let index = self.typed.parse::<usize>().ok();
+                    self.typed.clear();
+                    if let Some(index) = index.filter(|i| (1..=count).contains(i)) {
+                        return Some(Followed { index: index - 1, new_tab });
+                    }
+                },
+                _ => {
+                    let name = key.name();
+                    let Some(c) = name.chars().next().filter(|_| name.chars().count() == 1) else { continue; };
+
+                    match mode {
+                        LinkHints::Numbers if c.is_ascii_digit() => {
+                            self.typed.push(c);
+                            let n: usize = self.typed.parse().unwrap_or_default();
+                            // no more digits could make a valid link, don't wait for enter
+                            if n >= 1 && n <= count && n * 10 > count {
+                                self.typed.clear();
+                                return Some(Followed { index: n - 1, new_tab });
+                            }
+                        },
+                        LinkHints::Letters if c.is_ascii_alphabetic() => {
+                            self.typed.push(c.to_ascii_lowercase());
+                            let found = (0..count).find(|i| hint(mode, *i, count) == self.typed);
+                            if let Some(index) = found {
+                                self.typed.clear();
+                                return Some(Followed { index, new_tab });
+                            }
+                            if !(0..count).any(|i| hint(mode, i, count).starts_with(&self.typed)) {
+                                self.typed.clear();
+                            }
+                        },
+                        _ => {},
+                    }
+                },
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,137 @@
+//! Images from image links, fetched in the background and shown under the link.
+
+use std::{collections::HashMap, sync::{Mutex, MutexGuard, OnceLock}, thread, time::{Duration, Instant}};
+
+use crate::gemini;
+
+/// Largest width or height of a texture, bigger images are scaled down.
+pub const MAX_SIZE: u32 = 2048;
+
+/// Largest image fetched, in bytes.
+const MAX_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Most images kept, the ones shown longest ago are dropped first and fetched again if they're needed.
+const MAX_CACHED: usize = 32;
+
+/// Images shown this recently are kept even if there are more than [`MAX_CACHED`], as they're likely still on screen.
+const RECENT: Duration = Duration::from_secs(5);
+
+/// Height images are shown at until clicked.
+const PREVIEW_HEIGHT: f32 = 240.0;
+
+const EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+enum Image {
+    Loading,
+    /// Waiting to be uploaded, which has to happen on the UI thread.
+    Decoded(egui::ColorImage),
+    Loaded(egui::TextureHandle),
+    Failed(String),
+}
+
+/// Images by url, with when they were last shown.
+static IMAGES: OnceLock<Mutex<HashMap<String, (Image, Instant)>>> = OnceLock::new();
+
+fn images() -> MutexGuard<'static, HashMap<String, (Image, Instant)>> {
+    IMAGES.get_or_init(Default::default).lock().expect("images lock poisoned")
+}
+
+/// Whether `url` is a gemini link to an image file, going by its extension.
+pub fn is_image_link(url: &url::Url) -> bool {
+    let extension = url.path().rsplit_once('.').map(|(_, e)| e.to_ascii_lowercase());
+    url.scheme() == gemini::SCHEME && extension.is_some_and(|e| EXTENSIONS.contains(&e.as_str()))
+}
+
+pub fn decode(bytes: &[u8]) -> Result<egui::ColorImage, String> {
+    let image = image::load_from_memory(bytes).map_err(|err| err.to_string())?;
+    Ok(colour_image(image))
+}
+
+/// `image` ready to be uploaded as a texture, scaled down if it's bigger than [`MAX_SIZE`].
+pub fn colour_image(image: image::DynamicImage) -> egui::ColorImage {
+    let image = if image.width() > MAX_SIZE || image.height() > MAX_SIZE {
+        image.thumbnail(MAX_SIZE, MAX_SIZE)
+    } else {
+        image
+    };
+
+    let rgba = image.to_rgba8();
+    egui::ColorImage::from_rgba_unmultiplied([rgba.width() as usize, rgba.height() as usize], rgba.as_raw())
+}
+
+fn fetch(ctx: egui::Context, url: url::Url) {
+    thread::spawn(move || {
+        let image = gemini::fetch(&url, MAX_BYTES).and_then(|(_, mimetype, body)| {
+            if !mimetype.starts_with("image/") {
+                return Err(format!("not an image, the server sent '{mimetype}'"));
+            }
+            decode(&body)
+        });
+
+        let image = match image {
+            Ok(image) => Image::Decoded(image),
+            Err(err) => Image::Failed(err),
+        };
+        images().insert(url.to_string(), (image, Instant::now()));
+        ctx.request_repaint();
+    });
+}
+
+/// Show the image at `url`, starting to fetch it the first time.
+///
+/// Images are shown at a limited height until clicked.
+pub fn show(ui: &mut egui::Ui, url: &url::Url) {
+    let mut images = images();
+    if !images.contains_key(url.as_str()) && images.len() >= MAX_CACHED {
+        forget_oldest(&mut images);
+    }
+    let (image, shown) = images.entry(url.to_string()).or_insert_with(|| {
+        fetch(ui.ctx().clone(), url.clone());
+        (Image::Loading, Instant::now())
+    });
+    *shown = Instant::now();
+
+    if let Image::Decoded(decoded) = image {
+        let decoded = std::mem::take(decoded);
+        *image = Image::Loaded(ui.ctx().load_texture(url.as_str(), decoded, egui::TextureOptions::LINEAR));
+    }
+
+    match image {
+        Image::Loading | Image::Decoded(_) => {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.weak("Loading image");
+            });
+        },
+        Image::Failed(err) => {
+            ui.weak(format!("Couldn't load image: {err}"));
+        },
+        Image::Loaded(texture) => {
+            let expanded_id = ui.id().with("expanded");
+            let expanded = ui.data(|d| d.get_temp::<bool>(expanded_id)).unwrap_or(false);
+
+            let max_height = if expanded { f32::INFINITY } else { PREVIEW_HEIGHT };
+            let response = ui.add(
+                egui::Image::new(egui::load::SizedTexture::from_handle(texture))
+                    .fit_to_original_size(1.0)
+                    .max_size(egui::vec2(ui.available_width(), max_height))
+                    .sense(egui::Sense::click())
+            ).on_hover_text(if expanded { "Click to shrink" } else { "Click to expand" });
+
+            if response.clicked() {
+                ui.data_mut(|d| d.insert_temp(expanded_id, !expanded));
+            }
+        },
+    }
+}
+
+/// Drop the image shown longest ago, and its texture with it, unless it was shown [`RECENT`]ly.
+fn forget_oldest(images: &mut HashMap<String, (Image, Instant)>) {
+    let oldest = images.iter()
+        .filter(|(_, (_, shown))| shown.elapsed() > RECENT)
+        .min_by_key(|(_, (_, shown))| *shown)
+        .map(|(url, _)| url.clone());
+    if let Some(url) = oldest {
+        images.remove(&url);
+    }
+}
@@ -0,0 +1,149 @@
+//! Mistakes in gemtext that the parser accepts without complaint.
+
+use std::{fmt, fs, io::{self, Read}};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Counting from 1.
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.line, self.severity, self.message)
+    }
+}
+
+/// Check gemtext `source`, links are resolved against `url`.
+pub fn lint(source: &str, url: &url::Url) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let mut diagnose = |line: usize, severity: Severity, message: String| {
+        diagnostics.push(Diagnostic { line: line + 1, severity, message });
+    };
+
+    // line the open preformatted block started on
+    let mut preformatted = None;
+
+    for (i, line) in source.lines().enumerate() {
+        if line.starts_with("```") {
+            preformatted = match preformatted {
+                Some(_) => None,
+                None => Some(i),
+            };
+            continue;
+        }
+        if preformatted.is_some() {
+            continue;
+        }
+
+        if line.starts_with("####") {
+            diagnose(i, Severity::Warning, "headings only go to level 3, this shows as a level 3 heading starting with '#'".into());
+        } else if let Some(link) = line.strip_prefix("=>") {
+            match link.split([' ', '\t']).find(|s| !s.is_empty()) {
+                None => diagnose(i, Severity::Error, "link has no URL, it is shown as text".into()),
+                Some(link) => if let Err(err) = url.join(link) {
+                    diagnose(i, Severity::Error, format!("link URL '{link}' doesn't resolve: {err}"));
+                },
+            }
+        }
+    }
+
+    if let Some(start) = preformatted {
+        diagnose(start, Severity::Error, "preformatted block is never closed, the rest of the page is preformatted".into());
+    }
+
+    diagnostics.sort_by_key(|d| d.line);
+    diagnostics
+}
+
+/// `vostok lint [--base URL] FILE...`, `-` reads standard input.
+///
+/// Prints diagnostics as `file:line: severity: message` and returns the exit code,
+/// which is 1 if there were any errors.
+pub fn cli(args: &[String]) -> i32 {
+    let mut base = url::Url::parse("gemini://localhost/").expect("unreachable");
+    let mut files = Vec::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--base" {
+            let Some(url) = args.next().and_then(|url| url::Url::parse(url).ok()) else {
+                eprintln!("--base needs an absolute URL");
+                return 2;
+            };
+            base = url;
+        } else {
+            files.push(arg);
+        }
+    }
+
+    if files.is_empty() {
+        eprintln!("usage: vostok lint [--base URL] FILE...");
+        return 2;
+    }
+
+    let mut failed = false;
+
+    for file in files {
+        let source = if file == "-" {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).map(|_| source)
+        } else {
+            fs::read_to_string(file)
+        };
+        let source = match source {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{file}: {err}");
+                return 2;
+            },
+        };
+
+        // relative links are resolved as though the file were published at its path under the base
+        let url = base.join(file).unwrap_or_else(|_| base.clone());
+
+        for diagnostic in lint(&source, &url) {
+            println!("{file}:{diagnostic}");
+            failed |= diagnostic.severity == Severity::Error;
+        }
+    }
+
+    if failed { 1 } else { 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_common_mistakes() {
+        let url = url::Url::parse("gemini://example.org/").unwrap();
+        let source = "# Fine\n#### Too deep\n=>\n=> http://[::1 Broken\n=> ok.gmi Fine\n```\nnever closed";
+
+        let found: Vec<(usize, Severity)> = lint(source, &url).iter().map(|d| (d.line, d.severity)).collect();
+
+        assert_eq!(found, [
+            (2, Severity::Warning),
+            (3, Severity::Error),
+            (4, Severity::Error),
+            (6, Severity::Error),
+        ]);
+    }
+}
@@ -1,6 +1,29 @@
+mod ansi;
+mod bidi;
+mod bookmarks;
+mod downloads;
+mod feeds;
+mod find;
 mod gemtext;
 mod gemini;
+mod handlers;
+mod hints;
+mod images;
+mod lint;
+mod markdown;
+mod pageinfo;
+mod palette;
+mod plaintext;
+mod selection;
+mod settings;
+mod source;
+mod syntax;
 mod tab;
+mod theme;
+mod tofu;
+mod viewer;
+mod visited;
+mod xmlfeed;
 
 struct PromptWindow {
     prompt: String,
@@ -8,10 +31,41 @@ struct PromptWindow {
     input: String,
 }
 
+/// Choosing a name for a link being saved.
+struct SaveWindow {
+    url: url::Url,
+    name: String,
+    error: Option<String>,
+}
+
+/// Asking before running another program for the first time.
+struct LaunchWindow {
+    launch: handlers::Launch,
+    error: Option<String>,
+}
+
+impl LaunchWindow {
+    /// Run `launch` straight away if it's been agreed to before, otherwise ask first.
+    fn start(launch: handlers::Launch) -> Option<LaunchWindow> {
+        if !launch.is_confirmed() {
+            return Some(LaunchWindow { launch, error: None });
+        }
+        launch.run().err().map(|err| LaunchWindow { launch, error: Some(err.to_string()) })
+    }
+}
+
 struct App {
     tabs: Vec<(Vec<tab::Tab>, usize)>,
     current_tab: usize,
     window: Option<PromptWindow>,
+    save_window: Option<SaveWindow>,
+    launch_window: Option<LaunchWindow>,
+    find: Option<find::Find>,
+    hints: hints::HintInput,
+    settings: settings::Settings,
+    show_settings: bool,
+    show_lint: bool,
+    show_page_info: bool,
     progress: f32,
     target_progress: f32,
 }
@@ -19,6 +73,10 @@ struct App {
 impl App {
     pub const PROGRESS_APPROACH: f32 = 0.8;
 
+    fn icon(icon: &str) -> egui::RichText {
+        egui::RichText::new(icon).family(egui::FontFamily::Name("icons".into()))
+    }
+
     pub fn new(cc: &eframe::CreationContext) -> App {
         let mut font_defs = egui::FontDefinitions::default();
         font_defs.font_data.insert("ubuntu".into(), egui::FontData::from_static(
@@ -27,29 +85,48 @@ impl App {
         font_defs.font_data.insert("ubuntu_mono".into(), egui::FontData::from_static(
             include_bytes!("../fonts/ubuntu_mono.ttf")
         ));
+        font_defs.font_data.insert("serif".into(), egui::FontData::from_static(
+            include_bytes!("../fonts/dejavu_serif.ttf")
+        ));
         font_defs.font_data.insert("icons".into(), egui::FontData::from_static(
             include_bytes!("../fonts/icons.ttf")
         ));
 
         font_defs.families.entry(egui::FontFamily::Proportional).or_default().insert(0, "ubuntu".into());
         font_defs.families.entry(egui::FontFamily::Monospace).or_default().insert(0, "ubuntu_mono".into());
+        // anything the serif font lacks, like emoji, comes from the proportional fonts
+        let mut serif = vec!["serif".to_owned()];
+        serif.extend(font_defs.families[&egui::FontFamily::Proportional].iter().cloned());
+        font_defs.families.insert(egui::FontFamily::Name("serif".into()), serif);
         font_defs.families.entry(egui::FontFamily::Name("icons".into())).or_default().insert(0, "icons".into());
         
         cc.egui_ctx.set_fonts(font_defs);
-        cc.egui_ctx.set_style(egui::Style {
-            visuals: egui::Visuals {
-                dark_mode: true,
-                ..Default::default()
-            },
-            ..Default::default()
-        });
 
         cc.egui_ctx.set_zoom_factor(14.0/12.0);
+
+        let settings = cc.storage
+            .and_then(|storage| eframe::get_value(storage, settings::Settings::STORAGE_KEY))
+            .unwrap_or_default();
+        feeds::load(cc.storage);
+        bookmarks::load(cc.storage);
+        visited::load(cc.storage);
+        downloads::load(cc.storage);
+        handlers::load(cc.storage);
+        tofu::load(cc.storage);
+        theme::load();
         
         App {
             tabs: vec![(vec![Default::default()], 0)],
             current_tab: 0,
             window: None,
+            save_window: None,
+            launch_window: None,
+            find: None,
+            hints: Default::default(),
+            settings,
+            show_settings: false,
+            show_lint: false,
+            show_page_info: false,
             progress: 0.0,
             target_progress: 0.0,
         }
@@ -57,17 +134,39 @@ impl App {
 }
 
 impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         let mut tab_delta = 0i32;
+
+        // eframe also resets the visuals when the desktop's theme changes
+        let theme = theme::get(&self.settings.theme, frame.info().system_theme);
+        let visuals = theme.visuals();
+        if ctx.style().visuals != visuals {
+            ctx.set_visuals(visuals);
+        }
+
+        feeds::refresh_if_due();
+        downloads::set_directory(&self.settings.download_directory);
+        handlers::set(&self.settings.handlers);
+
+        let find_id = egui::Id::new("find_query");
+        if ctx.input_mut(|i| i.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::F))) {
+            self.find.get_or_insert_with(Default::default);
+            ctx.memory_mut(|m| m.request_focus(find_id));
+        }
         
         egui::TopBottomPanel::top("tab_list").show(ctx, |ui| {
             ui.horizontal(|ui| {
                 for (i, (history, current)) in self.tabs.iter().enumerate() {
-                    if ui.button(history[*current].title()).clicked() {
+                    let tab = &history[*current];
+                    let title = match tab.capsule().and_then(|host| self.settings.palette_for(host)) {
+                        Some(palette) => format!("{} {}", palette.emoji, tab.title()),
+                        None => tab.title().into(),
+                    };
+                    if ui.button(title).clicked() {
                         self.current_tab = i;
                     }
                 }
-                if ui.button(egui::RichText::new("+").family(egui::FontFamily::Name("icons".into()))).clicked() {
+                if ui.button(Self::icon("+")).clicked() {
                     self.current_tab = self.tabs.len();
                     self.tabs.push((vec![Default::default()], 0));
                 }
@@ -82,7 +181,9 @@ impl eframe::App for App {
                     if let Some(t) = tab.resolve() {
                         self.target_progress = 1.5;
                         match t {
-                            Ok(t) => {
+                            Ok(t) => if history[*current].is_blank() {
+                                history[*current] = t;
+                            } else {
                                 // remove future history, new branch
                                 *current += 1;
                                 history.drain(*current..);
@@ -90,6 +191,7 @@ impl eframe::App for App {
                             },
                             Err(action) => match action {
                                 tab::ActionRequired::Input { sensitive, prompt } => self.window = Some(PromptWindow { prompt, sensitive, input: String::new() }),
+                                tab::ActionRequired::Launch(launch) => self.launch_window = LaunchWindow::start(launch),
                             },
                         }
                     }
@@ -100,25 +202,107 @@ impl eframe::App for App {
                 }
             };
             
+            if tab.receive() {
+                ctx.request_repaint_after(std::time::Duration::from_millis(50));
+            }
+
+            // the downloads page follows their progress, made again whenever the list changes
+            if tab.url().as_str() == downloads::PAGE_URL && !tab.loading() {
+                let version = downloads::version();
+                let id = egui::Id::new("downloads_version").with(tab.content().id());
+                if ctx.data(|d| d.get_temp::<u64>(id)) != Some(version) {
+                    tab.refresh_downloads();
+                    ctx.data_mut(|d| d.insert_temp(egui::Id::new("downloads_version").with(tab.content().id()), version));
+                }
+                if downloads::any_active() {
+                    ctx.request_repaint_after(std::time::Duration::from_millis(250));
+                }
+            }
+
             ui.horizontal(|ui| {
                 // if ui.button("\u{2190}").clicked() {
-                if ui.button(egui::RichText::new("\u{f060}").family(egui::FontFamily::Name("icons".into()))).clicked() {
+                if ui.button(Self::icon("\u{f060}")).clicked() {
                     tab_delta = -1;
                 }
                 // if ui.button("\u{2192}").clicked() {
-                if ui.button(egui::RichText::new("\u{f061}").family(egui::FontFamily::Name("icons".into()))).clicked() {
+                if ui.button(Self::icon("\u{f061}")).clicked() {
                     tab_delta = 1;
                 }
                 // if ui.button("\u{27F3}").clicked() {
-                if ui.button(egui::RichText::new("\u{f2f9}").family(egui::FontFamily::Name("icons".into()))).clicked() {
+                if ui.button(Self::icon("\u{f2f9}")).clicked() {
                     tab.request(tab.url().clone());
                     self.target_progress = Self::PROGRESS_APPROACH;
                     self.progress = 0.0;
                 }
+                if tab.url().scheme() == gemini::SCHEME {
+                    let bookmarked = bookmarks::is_bookmarked(tab.url().as_str());
+                    let res = ui.add(egui::Button::new(Self::icon("\u{f02e}")).selected(bookmarked))
+                        .on_hover_text(if bookmarked { "Remove bookmark" } else { "Bookmark this page" });
+                    if res.clicked() {
+                        if bookmarked {
+                            bookmarks::remove(tab.url().as_str());
+                        } else {
+                            bookmarks::add(tab.url().as_str(), tab.title());
+                        }
+                    }
+
+                    let subscribed = feeds::is_subscribed(tab.url());
+                    let res = ui.add(egui::Button::new(Self::icon("\u{f09e}")).selected(subscribed))
+                        .on_hover_text(if subscribed { "Unsubscribe" } else { "Subscribe to this page's feed" });
+                    if res.clicked() {
+                        if subscribed {
+                            feeds::unsubscribe(tab.url());
+                        } else {
+                            feeds::subscribe(tab.url(), tab.title(), tab.content());
+                        }
+                    }
+                }
+                if tab.content().is_plain() {
+                    let res = ui.add(egui::Button::new(Self::icon("\u{f035}")).selected(self.settings.plain_text_wrap))
+                        .on_hover_text(if self.settings.plain_text_wrap { "Don't wrap lines" } else { "Wrap lines" });
+                    if res.clicked() {
+                        self.settings.plain_text_wrap = !self.settings.plain_text_wrap;
+                    }
+
+                    let res = ui.add(egui::Button::new(Self::icon("\u{f0cb}")).selected(self.settings.plain_text_line_numbers))
+                        .on_hover_text(if self.settings.plain_text_line_numbers { "Hide line numbers" } else { "Show line numbers" });
+                    if res.clicked() {
+                        self.settings.plain_text_line_numbers = !self.settings.plain_text_line_numbers;
+                    }
+                }
+                if !tab.diagnostics().is_empty() {
+                    let count = tab.diagnostics().len();
+                    let res = ui.add(egui::Button::new(Self::icon("\u{f071}")).selected(self.show_lint))
+                        .on_hover_text(format!("{count} problem{} in this page's gemtext", if count == 1 { "" } else { "s" }));
+                    if res.clicked() {
+                        self.show_lint = !self.show_lint;
+                    }
+                }
+                let view_source = ui.input_mut(|i| i.consume_shortcut(&egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::U)));
+                if tab.raw().is_some() && tab.url().scheme() != source::SCHEME && (ui.button(Self::icon("\u{f121}")).on_hover_text("View source").clicked() || view_source) {
+                    if let Some(url) = source::url(tab.url()) {
+                        tab.request(url);
+                        self.target_progress = Self::PROGRESS_APPROACH;
+                        self.progress = 0.0;
+                    }
+                }
+                let res = ui.add(egui::Button::new(Self::icon("\u{f05a}")).selected(self.show_page_info)).on_hover_text("Page info");
+                if res.clicked() {
+                    self.show_page_info = !self.show_page_info;
+                }
+                if ui.button(Self::icon("\u{f013}")).on_hover_text("Settings").clicked() {
+                    self.show_settings = !self.show_settings;
+                }
                 let res = ui.add_enabled(!tab.loading(), egui::TextEdit::singleline(&mut tab.display_url).desired_width(f32::INFINITY));
                 // pressed enter navigate to url
                 if res.lost_focus() && res.ctx.input(|i| i.key_pressed(egui::Key::Enter)) {
-                    let url = if !tab.display_url.starts_with(&format!("{}://", gemini::SCHEME)) {
+                    let url = if tab.display_url.starts_with("about:") {
+                        // about:feeds has no host, browser pages are looked up by it
+                        format!("about://{}", tab.display_url.trim_start_matches("about:").trim_start_matches('/'))
+                    } else if let Some(page) = tab.display_url.strip_prefix(&format!("{}:", source::SCHEME)) {
+                        // the page's url can leave out the scheme too
+                        if page.contains("://") { tab.display_url.clone() } else { format!("{}:{}://{page}", source::SCHEME, gemini::SCHEME) }
+                    } else if !tab.display_url.starts_with(&format!("{}://", gemini::SCHEME)) {
                         format!("{}://{}", gemini::SCHEME, tab.display_url)
                     } else {
                         tab.display_url.clone()
@@ -130,6 +314,44 @@ impl eframe::App for App {
                     }
                 }
             });
+
+            let mut close_find = false;
+            if let Some(find) = &mut self.find {
+                ui.horizontal(|ui| {
+                    let res = ui.add(egui::TextEdit::singleline(&mut find.query).id(find_id).hint_text("Find in page").desired_width(240.0));
+                    if res.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        if ui.input(|i| i.modifiers.shift) {
+                            find.previous();
+                        } else {
+                            find.next();
+                        }
+                        res.request_focus();
+                    }
+                    if res.has_focus() && ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                        close_find = true;
+                    }
+                    ui.toggle_value(&mut find.case_sensitive, "Aa").on_hover_text("Match case");
+                    ui.toggle_value(&mut find.whole_word, "\"ab\"").on_hover_text("Whole words");
+                    if ui.button(Self::icon("\u{f062}")).on_hover_text("Previous match").clicked() {
+                        find.previous();
+                    }
+                    if ui.button(Self::icon("\u{f063}")).on_hover_text("Next match").clicked() {
+                        find.next();
+                    }
+                    if !find.query.is_empty() {
+                        match find.current() {
+                            Some(i) => ui.label(format!("{} of {}", i + 1, find.count())),
+                            None => ui.label("No matches"),
+                        };
+                    }
+                    if ui.button(Self::icon("\u{f00d}")).on_hover_text("Close").clicked() {
+                        close_find = true;
+                    }
+                });
+            }
+            if close_find {
+                self.find = None;
+            }
         });
         
         let tab = {
@@ -164,25 +386,172 @@ impl eframe::App for App {
         }
         self.progress += (self.target_progress - self.progress) * rate;
 
-        egui::CentralPanel::default().frame(egui::Frame::default().inner_margin(egui::Margin::ZERO).fill(egui::Color32::from_gray(10))).show(ctx, |ui| {
+        // for per capsule settings
+        let host = tab.capsule().map(str::to_owned);
+        let page_theme = match host.as_deref().and_then(|host| self.settings.palette_for(host)) {
+            Some(palette) => palette.apply(&theme),
+            None => theme,
+        };
+
+        if self.show_lint && !tab.diagnostics().is_empty() {
+            egui::TopBottomPanel::bottom("lint").resizable(true).show(ctx, |ui| {
+                egui::ScrollArea::vertical().auto_shrink([false, true]).show(ui, |ui| {
+                    for diagnostic in tab.diagnostics() {
+                        let colour = match diagnostic.severity {
+                            lint::Severity::Error => ui.visuals().error_fg_color,
+                            lint::Severity::Warning => ui.visuals().warn_fg_color,
+                        };
+                        ui.horizontal(|ui| {
+                            ui.label(egui::RichText::new(format!("Line {}", diagnostic.line)).monospace().weak());
+                            ui.label(egui::RichText::new(diagnostic.severity.to_string()).color(colour));
+                            ui.label(&diagnostic.message);
+                        });
+                    }
+                });
+            });
+        }
+
+        if self.show_page_info {
+            egui::SidePanel::right("page_info").resizable(true).show(ctx, |ui| pageinfo::ui(ui, tab));
+        }
+
+        let mut new_tab = None;
+        let mut followed = self.hints.update(ctx, self.settings.link_hints, tab.content().link_count())
+            .and_then(|f| Some((
+                tab.content().link(f.index)?.to_owned(),
+                if f.new_tab { gemtext::Target::NewTab } else { gemtext::Target::Current },
+            )));
+        let mut hovered = None;
+
+        let page = egui::CentralPanel::default().frame(egui::Frame::default().inner_margin(egui::Margin::ZERO).fill(page_theme.background)).show(ctx, |ui| {
             ui.add(egui::ProgressBar::new(self.progress).rounding(egui::Rounding::default()).desired_height(2.0).animate(true));
-            egui::ScrollArea::vertical().auto_shrink(false).show(ui, |ui| {
-                let margin = ((ui.available_width() - 800.0) / 2.0).max(8.0);
+            if let Some(viewer) = tab.viewer_mut() {
+                egui::Frame::none().inner_margin(egui::Margin::same(4.0)).show(ui, |ui| viewer.ui(ui));
+                return;
+            }
+            // unwrapped plain text scrolls sideways
+            let horizontal = tab.content().is_plain() && !self.settings.plain_text_wrap;
+            egui::ScrollArea::new([horizontal, true]).auto_shrink(false).show(ui, |ui| {
+                let margin = ((ui.available_width() - self.settings.typography.content_width) / 2.0).max(8.0);
                 
-                egui::Frame::default().outer_margin(egui::Margin::symmetric(margin, 4.0)).show(ui, |ui| {let mut new_url = None;
-                    tab.content().render(ui, &mut new_url);
-                    
-                    if let Some(url) = new_url {
-                        if let Ok(url) = tab.url().join(&url) {
-                            tab.request(url);
-                            self.target_progress = Self::PROGRESS_APPROACH;
-                            self.progress = 0.0;
-                        }
+                egui::Frame::default().outer_margin(egui::Margin::symmetric(margin, 4.0)).show(ui, |ui| {
+                    if let Some(find) = &mut self.find {
+                        find.update(tab.content());
+                    }
+                    let links = tab.content().render(ui, tab.url(), self.find.as_mut(), &self.settings, &page_theme, self.hints.typed());
+
+                    if links.followed.is_some() {
+                        followed = links.followed;
                     }
+                    hovered = links.hovered;
                 });
             });
         });
 
+        // status bar, over the bottom of the page like most browsers
+        if let Some(url) = hovered {
+            egui::Area::new(egui::Id::new("status"))
+                .order(egui::Order::Foreground)
+                .pivot(egui::Align2::LEFT_BOTTOM)
+                .fixed_pos(page.response.rect.left_bottom())
+                .interactable(false)
+                .show(ctx, |ui| {
+                    egui::Frame::popup(ui.style()).rounding(egui::Rounding::ZERO).show(ui, |ui| {
+                        ui.label(url);
+                    });
+                });
+        }
+
+        if let Some((url, target)) = followed {
+            if target == gemtext::Target::Download {
+                if let Ok(url) = tab.url().join(&url) {
+                    self.save_window = Some(SaveWindow { name: downloads::file_name(&url), url, error: None });
+                }
+            } else if url.starts_with("http://") || url.starts_with("https://") {
+                visited::visit(&url);
+                // the browser opens web links, unless there's a handler for them
+                match url::Url::parse(&url).ok().map(|url| handlers::for_url(&url)).filter(|l| l.command.is_some()) {
+                    Some(launch) => self.launch_window = LaunchWindow::start(launch),
+                    None => ctx.open_url(egui::OpenUrl { url, new_tab: target != gemtext::Target::Current }),
+                }
+            } else if let Ok(url) = tab.url().join(&url) {
+                if target == gemtext::Target::Current {
+                    tab.request(url);
+                    self.target_progress = Self::PROGRESS_APPROACH;
+                    self.progress = 0.0;
+                } else {
+                    new_tab = Some((url, target == gemtext::Target::BackgroundTab));
+                }
+            }
+        }
+
+        if let Some((url, background)) = new_tab {
+            self.tabs.push((vec![tab::Tab::new(url)], 0));
+            if !background {
+                self.current_tab = self.tabs.len() - 1;
+                self.target_progress = Self::PROGRESS_APPROACH;
+                self.progress = 0.0;
+            }
+        }
+
+        egui::Window::new("Settings").open(&mut self.show_settings).show(ctx, |ui| {
+            self.settings.ui(ui, host.as_deref());
+        });
+
+        let mut close_launch = false;
+        if let Some(window) = &mut self.launch_window {
+            egui::Window::new("Open with another program").collapsible(false).show(ctx, |ui| {
+                ui.label(format!("Open {} with {}?", window.launch.argument, window.launch.name()));
+                ui.weak(format!("You won't be asked again before opening {} with it.", window.launch.kind));
+                if let Some(err) = &window.error {
+                    ui.colored_label(ui.visuals().error_fg_color, format!("Couldn't run it: {err}"));
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Open").clicked() {
+                        match window.launch.run() {
+                            Ok(()) => close_launch = true,
+                            Err(err) => window.error = Some(err.to_string()),
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close_launch = true;
+                    }
+                });
+            });
+        }
+        if close_launch {
+            self.launch_window = None;
+        }
+
+        let mut close_save = false;
+        if let Some(window) = &mut self.save_window {
+            egui::Window::new("Save link as\u{2026}").collapsible(false).show(ctx, |ui| {
+                ui.label(window.url.as_str());
+                let dir = downloads::directory().map(|d| d.display().to_string()).unwrap_or_default();
+                ui.horizontal(|ui| {
+                    ui.weak(format!("{dir}{}", std::path::MAIN_SEPARATOR));
+                    ui.add(egui::TextEdit::singleline(&mut window.name).desired_width(240.0));
+                });
+                if let Some(err) = &window.error {
+                    ui.colored_label(ui.visuals().error_fg_color, err);
+                }
+                ui.horizontal(|ui| {
+                    if ui.button("Save").clicked() {
+                        match downloads::start(window.url.clone(), window.name.trim()) {
+                            Ok(()) => close_save = true,
+                            Err(err) => window.error = Some(format!("Couldn't save: {err}")),
+                        }
+                    }
+                    if ui.button("Cancel").clicked() {
+                        close_save = true;
+                    }
+                });
+            });
+        }
+        if close_save {
+            self.save_window = None;
+        }
+
         let mut close_window = false;
         if let Some(window) = &mut self.window {
             egui::Window::new(egui::RichText::new(&window.prompt).text_style(egui::TextStyle::Body)).show(ctx, |ui| {
@@ -198,12 +567,31 @@ impl eframe::App for App {
             });
         }
     }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, settings::Settings::STORAGE_KEY, &self.settings);
+        feeds::save(storage);
+        bookmarks::save(storage);
+        visited::save(storage);
+        downloads::save(storage);
+        handlers::save(storage);
+        tofu::save(storage);
+    }
 }
 
 fn main() {
     env_logger::init();
+
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|a| a == "lint") {
+        std::process::exit(lint::cli(&args[2..]));
+    }
     
-    if let Err(err) = eframe::run_native("Vostok", eframe::NativeOptions::default(), Box::new(|cc| Box::new(App::new(cc)))) {
+    let options = eframe::NativeOptions {
+        follow_system_theme: true,
+        ..Default::default()
+    };
+    if let Err(err) = eframe::run_native("Vostok", options, Box::new(|cc| Box::new(App::new(cc)))) {
         eprintln!("{err}");
     }
 }
@@ -0,0 +1,417 @@
+//! Markdown documents, CommonMark plus tables, turned into lines of a page.
+
+use std::ops::Range;
+
+pub use pulldown_cmark::Alignment;
+use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, Options, Tag, TagEnd};
+
+use crate::gemtext::{GemLine, Preformatted};
+
+/// How a run of text is shown.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Style {
+    pub strong: bool,
+    pub emphasis: bool,
+    pub strikethrough: bool,
+    pub code: bool,
+    /// Url of the link the text is in, as written.
+    pub link: Option<String>,
+}
+
+/// Text with runs of styles, like a paragraph or a table cell.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Inline {
+    pub text: String,
+    /// Byte ranges of the text and their styles, in order and covering all of it.
+    pub spans: Vec<(Range<usize>, Style)>,
+}
+
+impl Inline {
+    fn push(&mut self, text: &str, style: &Style) {
+        let start = self.text.len();
+        self.text += text;
+        match self.spans.last_mut() {
+            Some((range, last)) if last == style => range.end = self.text.len(),
+            _ => self.spans.push((start..self.text.len(), style.clone())),
+        }
+    }
+
+    /// Byte ranges of the text that are links and their urls, a link in more than one style is still one link.
+    pub fn links(&self) -> Vec<(Range<usize>, &str)> {
+        let mut links: Vec<(Range<usize>, &str)> = Vec::new();
+        for (range, style) in &self.spans {
+            let Some(url) = style.link.as_deref() else {
+                continue;
+            };
+            match links.last_mut() {
+                Some((last, last_url)) if last.end == range.start && *last_url == url => last.end = range.end,
+                _ => links.push((range.clone(), url)),
+            }
+        }
+        links
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub alignments: Vec<Alignment>,
+    pub header: Vec<Inline>,
+    pub rows: Vec<Vec<Inline>>,
+    /// Every cell's text, separated by tabs and rows by newlines, for finding and copying.
+    pub text: String,
+}
+
+impl Table {
+    fn new(alignments: Vec<Alignment>, header: Vec<Inline>, rows: Vec<Vec<Inline>>) -> Table {
+        let text = std::iter::once(&header).chain(&rows)
+            .map(|row| row.iter().map(|cell| cell.text.as_str()).collect::<Vec<_>>().join("\t"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        Table { alignments, header, rows, text }
+    }
+
+    /// Every cell, a row at a time with the header first, and the byte offset of its text in [`Table::text`].
+    pub fn cells(&self) -> impl Iterator<Item = (usize, usize, usize, &Inline)> {
+        let mut start = 0;
+        std::iter::once(&self.header).chain(&self.rows)
+            .enumerate()
+            .flat_map(|(r, row)| row.iter().enumerate().map(move |(c, cell)| (r, c, cell)))
+            .map(move |(r, c, cell)| {
+                let offset = start;
+                start += cell.text.len() + 1;
+                (r, c, offset, cell)
+            })
+    }
+}
+
+/// A block of a markdown document, code blocks are [`GemLine::Preformatted`] instead.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Paragraph(Inline),
+    /// Level from 1 to 6.
+    Heading(u8, Inline),
+    /// `marker` is the bullet or number, empty for later paragraphs of the same item.
+    ListItem { depth: usize, marker: String, text: Inline },
+    /// Nested `depth` quotes deep.
+    Quote(usize, Inline),
+    Rule,
+    Table(Table),
+}
+
+impl Block {
+    /// The text shown for the block, rules have none.
+    pub fn text(&self) -> &str {
+        match self {
+            Block::Paragraph(inline) | Block::Heading(_, inline) | Block::Quote(_, inline) => &inline.text,
+            Block::ListItem { text, .. } => &text.text,
+            Block::Table(table) => &table.text,
+            Block::Rule => "",
+        }
+    }
+
+    /// Byte ranges of [`Block::text`] that are links and their urls.
+    pub fn links(&self) -> Vec<(Range<usize>, &str)> {
+        match self {
+            Block::Paragraph(inline) | Block::Heading(_, inline) | Block::Quote(_, inline) => inline.links(),
+            Block::ListItem { text, .. } => text.links(),
+            Block::Table(table) => table.cells()
+                .flat_map(|(_, _, start, cell)| cell.links().into_iter().map(move |(range, url)| (range.start + start..range.end + start, url)))
+                .collect(),
+            Block::Rule => Vec::new(),
+        }
+    }
+}
+
+/// Builds blocks from parser events.
+#[derive(Default)]
+struct Builder {
+    lines: Vec<GemLine>,
+    /// Text of the block being built.
+    inline: Inline,
+    style: Style,
+    /// Nesting of strong, emphasis and strikethrough, which can overlap.
+    strong: usize,
+    emphasis: usize,
+    strikethrough: usize,
+    heading: Option<u8>,
+    quotes: usize,
+    /// Next number of each open list, `None` for bulleted lists.
+    lists: Vec<Option<u64>>,
+    /// Marker of a list item whose text hasn't been finished yet.
+    marker: Option<String>,
+    /// Language and contents of the code block being built.
+    code: Option<(String, String)>,
+    /// The table being built, its text is filled in at the end.
+    table: Option<Table>,
+    row: Vec<Inline>,
+    /// Url of the image whose alt text is being built, and whether it's in a link, which the text then belongs to.
+    image: Option<(String, bool)>,
+}
+
+impl Builder {
+    fn text(&mut self, text: &str) {
+        if let Some((_, contents)) = &mut self.code {
+            *contents += text;
+        } else {
+            self.style.strong = self.strong > 0;
+            self.style.emphasis = self.emphasis > 0;
+            self.style.strikethrough = self.strikethrough > 0;
+            self.inline.push(text, &self.style);
+        }
+    }
+
+    /// Finish the current block of text, if there is one.
+    fn flush(&mut self) {
+        // an item's marker is shown even if it has no text of its own, like one holding a nested list
+        if self.inline.text.trim().is_empty() && self.marker.is_none() {
+            self.inline = Inline::default();
+            return;
+        }
+
+        let text = std::mem::take(&mut self.inline);
+        let block = if let Some(level) = self.heading {
+            Block::Heading(level, text)
+        } else if !self.lists.is_empty() {
+            Block::ListItem {
+                depth: self.lists.len(),
+                marker: self.marker.take().unwrap_or_default(),
+                text,
+            }
+        } else if self.quotes > 0 {
+            Block::Quote(self.quotes, text)
+        } else {
+            Block::Paragraph(text)
+        };
+        self.lines.push(GemLine::Markdown(block));
+    }
+
+    fn start(&mut self, tag: Tag) {
+        match tag {
+            // text can come before a paragraph without one of its own, like html or a tight list item's
+            Tag::Paragraph | Tag::HtmlBlock if !self.inline.text.trim().is_empty() => self.flush(),
+            Tag::Heading { level, .. } => {
+                self.flush();
+                self.heading = Some(heading_level(level));
+            },
+            Tag::BlockQuote(_) => {
+                self.flush();
+                self.quotes += 1;
+            },
+            Tag::CodeBlock(kind) => {
+                self.flush();
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                self.code = Some((language, String::new()));
+            },
+            Tag::List(first) => {
+                self.flush();
+                self.lists.push(first);
+            },
+            Tag::Item => {
+                self.flush();
+                self.marker = Some(match self.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    },
+                    _ => "\u{2022}".into(),
+                });
+            },
+            Tag::Table(alignments) => {
+                self.flush();
+                self.table = Some(Table::new(alignments, Vec::new(), Vec::new()));
+            },
+            Tag::Emphasis => self.emphasis += 1,
+            Tag::Strong => self.strong += 1,
+            Tag::Strikethrough => self.strikethrough += 1,
+            Tag::Link { dest_url, .. } => self.style.link = Some(dest_url.to_string()),
+            Tag::Image { dest_url, .. } => {
+                let in_link = self.style.link.is_some();
+                if !in_link {
+                    self.style.link = Some(dest_url.to_string());
+                }
+                self.image = Some((dest_url.to_string(), in_link));
+                // images are shown as links to them, labelled with their alt text or the url if there isn't any
+                self.inline.push("\u{1F5BC} ", &self.style);
+            },
+            _ => {},
+        }
+    }
+
+    fn end(&mut self, tag: TagEnd) {
+        match tag {
+            TagEnd::Paragraph | TagEnd::HtmlBlock => self.flush(),
+            TagEnd::Heading(_) => {
+                self.flush();
+                self.heading = None;
+            },
+            TagEnd::BlockQuote(_) => {
+                self.flush();
+                self.quotes -= 1;
+            },
+            TagEnd::CodeBlock => {
+                if let Some((language, contents)) = self.code.take() {
+                    let contents = contents.strip_suffix('\n').unwrap_or(&contents);
+                    self.lines.push(GemLine::Preformatted(Preformatted::new(&language, contents)));
+                }
+            },
+            TagEnd::List(_) => {
+                self.flush();
+                self.lists.pop();
+            },
+            TagEnd::Item => self.flush(),
+            TagEnd::TableCell => self.row.push(std::mem::take(&mut self.inline)),
+            TagEnd::TableHead => {
+                if let Some(table) = &mut self.table {
+                    table.header = std::mem::take(&mut self.row);
+                }
+            },
+            TagEnd::TableRow => {
+                if let Some(table) = &mut self.table {
+                    table.rows.push(std::mem::take(&mut self.row));
+                }
+            },
+            TagEnd::Table => {
+                if let Some(table) = self.table.take() {
+                    self.lines.push(GemLine::Markdown(Block::Table(Table::new(table.alignments, table.header, table.rows))));
+                }
+            },
+            TagEnd::Emphasis => self.emphasis -= 1,
+            TagEnd::Strong => self.strong -= 1,
+            TagEnd::Strikethrough => self.strikethrough -= 1,
+            TagEnd::Link => self.style.link = None,
+            TagEnd::Image => {
+                if let Some((url, in_link)) = self.image.take() {
+                    if self.inline.text.ends_with("\u{1F5BC} ") {
+                        self.text(&url);
+                    }
+                    if !in_link {
+                        self.style.link = None;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+fn heading_level(level: HeadingLevel) -> u8 {
+    match level {
+        HeadingLevel::H1 => 1,
+        HeadingLevel::H2 => 2,
+        HeadingLevel::H3 => 3,
+        HeadingLevel::H4 => 4,
+        HeadingLevel::H5 => 5,
+        HeadingLevel::H6 => 6,
+    }
+}
+
+/// Lines of a page showing the markdown in `source`.
+pub fn lines(source: &str) -> Vec<GemLine> {
+    let mut builder = Builder::default();
+
+    for event in pulldown_cmark::Parser::new_ext(source, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH) {
+        match event {
+            Event::Start(tag) => builder.start(tag),
+            Event::End(tag) => builder.end(tag),
+            Event::Text(text) => builder.text(&text),
+            Event::Code(code) => {
+                builder.style.code = true;
+                builder.text(&code);
+                builder.style.code = false;
+            },
+            // there's no html renderer, so it's shown as written
+            Event::Html(html) | Event::InlineHtml(html) => builder.text(&html),
+            Event::SoftBreak => builder.text(" "),
+            Event::HardBreak => builder.text("\n"),
+            Event::Rule => {
+                builder.flush();
+                builder.lines.push(GemLine::Markdown(Block::Rule));
+            },
+            _ => {},
+        }
+    }
+    builder.flush();
+
+    builder.lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inline(spans: &[(&str, Style)]) -> Inline {
+        let mut inline = Inline::default();
+        for (text, style) in spans {
+            inline.push(text, style);
+        }
+        inline
+    }
+
+    #[test]
+    fn markdown_becomes_blocks() {
+        let source = "# Title\n\nSome *very **strong*** text, [a link](other.md).\n\n\
+            1. One\n2. Two\n   - Nested\n\n> Quoted\n\n```rust\nfn main() {}\n```\n\n---\n\n\
+            | Name | Size |\n|:-----|-----:|\n| a `b` | 1 |\n";
+        let lines = lines(source);
+
+        let emphasis = Style { emphasis: true, ..Default::default() };
+        let link = Style { link: Some("other.md".into()), ..Default::default() };
+        assert_eq!(lines, [
+            GemLine::Markdown(Block::Heading(1, inline(&[("Title", Style::default())]))),
+            GemLine::Markdown(Block::Paragraph(inline(&[
+                ("Some ", Style::default()),
+                ("very ", emphasis.clone()),
+                ("strong", Style { strong: true, ..emphasis }),
+                (" text, ", Style::default()),
+                ("a link", link),
+                (".", Style::default()),
+            ]))),
+            GemLine::Markdown(Block::ListItem { depth: 1, marker: "1.".into(), text: inline(&[("One", Style::default())]) }),
+            GemLine::Markdown(Block::ListItem { depth: 1, marker: "2.".into(), text: inline(&[("Two", Style::default())]) }),
+            GemLine::Markdown(Block::ListItem { depth: 2, marker: "\u{2022}".into(), text: inline(&[("Nested", Style::default())]) }),
+            GemLine::Markdown(Block::Quote(1, inline(&[("Quoted", Style::default())]))),
+            GemLine::Preformatted(Preformatted::new("rust", "fn main() {}")),
+            GemLine::Markdown(Block::Rule),
+            GemLine::Markdown(Block::Table(Table::new(
+                vec![Alignment::Left, Alignment::Right],
+                vec![inline(&[("Name", Style::default())]), inline(&[("Size", Style::default())])],
+                vec![vec![
+                    inline(&[("a ", Style::default()), ("b", Style { code: true, ..Default::default() })]),
+                    inline(&[("1", Style::default())]),
+                ]],
+            ))),
+        ]);
+
+        let GemLine::Markdown(Block::Paragraph(paragraph)) = &lines[1] else { unreachable!() };
+        let link = paragraph.text.find("a link").unwrap();
+        assert_eq!(paragraph.links(), [(link..link + 6, "other.md")]);
+
+        assert_eq!(lines[8].text(), "Name\tSize\na b\t1");
+    }
+
+    #[test]
+    fn table_links_are_found_in_its_text() {
+        let lines = lines("| [a](one) | b |\n|---|---|\n| **[c](two)** [d](two) | e |\n");
+        let GemLine::Markdown(block) = &lines[0] else { panic!("{lines:?}") };
+        assert_eq!(block.text(), "a\tb\nc d\te");
+        // separate links to the same page are still separate
+        assert_eq!(block.links(), [(0..1, "one"), (4..5, "two"), (6..7, "two")]);
+    }
+
+    #[test]
+    fn images_and_html_are_kept_apart() {
+        let lines = lines("<div>\nhtml\n</div>\n\n[![ci](badge.svg)](https://ci) ![](logo.png) after");
+
+        let GemLine::Markdown(Block::Paragraph(html)) = &lines[0] else { panic!("{lines:?}") };
+        assert_eq!(html.text, "<div>\nhtml\n</div>\n");
+
+        let GemLine::Markdown(Block::Paragraph(paragraph)) = &lines[1] else { panic!("{lines:?}") };
+        assert_eq!(paragraph.text, "\u{1F5BC} ci \u{1F5BC} logo.png after");
+        let logo = paragraph.text.find("\u{1F5BC} logo").unwrap();
+        assert_eq!(paragraph.links(), [(0..logo - 1, "https://ci"), (logo..logo + 13, "logo.png")]);
+        assert_eq!(lines.len(), 2);
+    }
+}
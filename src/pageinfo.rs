@@ -0,0 +1,111 @@
+//! The page info panel, what the server sent for a page and how it arrived.
+
+use std::time::Duration;
+
+use crate::{gemini, tab, tofu, viewer};
+
+pub fn ui(ui: &mut egui::Ui, tab: &tab::Tab) {
+    egui::ScrollArea::vertical().auto_shrink([false, true]).show(ui, |ui| {
+        section(ui, "Page", |ui| {
+            row(ui, "Address", tab.url().as_str());
+            row(ui, "Title", tab.title());
+        });
+
+        let Some(raw) = tab.raw() else {
+            ui.weak("This page wasn't sent by a capsule.");
+            return;
+        };
+
+        section(ui, "Response", |ui| {
+            row(ui, "Status", raw.status.to_string());
+            row(ui, "Meta", raw.meta.as_str());
+            if let Some(info) = tab.info() {
+                row(ui, "Size", viewer::format_size(info.size));
+            }
+        });
+
+        if let Some(image) = tab.viewer().map(|v| &v.info) {
+            section(ui, "Image", |ui| {
+                row(ui, "Type", image.mimetype.as_str());
+                row(ui, "Dimensions", format!("{} \u{00D7} {}", image.width, image.height));
+                if image.frames > 1 {
+                    row(ui, "Frames", image.frames.to_string());
+                }
+            });
+        }
+
+        let Some(info) = tab.info() else {
+            return;
+        };
+
+        section(ui, "Connection", |ui| {
+            row(ui, "TLS version", info.tls_version.as_str());
+            row(ui, "Cipher", info.cipher.as_str());
+        });
+
+        match &info.certificate {
+            Some(certificate) => section(ui, "Certificate", |ui| certificate_rows(ui, certificate)),
+            None => {
+                ui.weak("The server's certificate couldn't be read.");
+            },
+        }
+
+        section(ui, "Timing", |ui| {
+            let timing = &info.timing;
+            row(ui, "DNS lookup", milliseconds(timing.dns));
+            row(ui, "Connected", milliseconds(timing.connect));
+            row(ui, "TLS handshake", milliseconds(timing.handshake));
+            row(ui, "First byte", milliseconds(timing.first_byte));
+            row(ui, "Total", milliseconds(timing.total));
+        });
+        ui.weak("Times are from the start of the request.");
+    });
+}
+
+fn certificate_rows(ui: &mut egui::Ui, certificate: &gemini::Certificate) {
+    let now = x509_parser::time::ASN1Time::now().timestamp();
+
+    row(ui, "Subject", certificate.subject.as_str());
+    row(ui, "Issuer", certificate.issuer.as_str());
+    row(ui, "Valid from", date(certificate.not_before));
+    if certificate.not_after < now {
+        row(ui, "Valid until", egui::RichText::new(format!("{} (expired)", date(certificate.not_after))).color(ui.visuals().warn_fg_color));
+    } else {
+        row(ui, "Valid until", date(certificate.not_after));
+    }
+    row(ui, "SHA-256", egui::RichText::new(&certificate.fingerprint).monospace());
+
+    let trust = match certificate.trust {
+        tofu::Trust::FirstUse => egui::RichText::new("First visit, this certificate is expected from now on"),
+        tofu::Trust::Known(since) => egui::RichText::new(format!("Matches the certificate first seen {}", date(since))),
+        tofu::Trust::Renewed => egui::RichText::new("Replaces a certificate that had expired"),
+        tofu::Trust::Changed(since) => egui::RichText::new(format!("Doesn't match the certificate first seen {}, which hasn't expired", date(since)))
+            .color(ui.visuals().warn_fg_color),
+    };
+    row(ui, "Trust", trust);
+}
+
+fn section(ui: &mut egui::Ui, title: &str, add_contents: impl FnOnce(&mut egui::Ui)) {
+    ui.strong(title);
+    egui::Grid::new(title).num_columns(2).spacing([12.0, 4.0]).show(ui, add_contents);
+    ui.add_space(8.0);
+}
+
+fn row(ui: &mut egui::Ui, name: &str, value: impl Into<egui::WidgetText>) {
+    ui.weak(name);
+    ui.add(egui::Label::new(value).wrap(true));
+    ui.end_row();
+}
+
+fn milliseconds(duration: Duration) -> String {
+    format!("{:.1} ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// A unix time as a UTC date and time, like `2024-05-21 10:00 UTC`.
+fn date(timestamp: i64) -> String {
+    let Ok(time) = x509_parser::time::ASN1Time::from_timestamp(timestamp) else {
+        return timestamp.to_string();
+    };
+    let time = time.to_datetime();
+    format!("{}-{:02}-{:02} {:02}:{:02} UTC", time.year(), time.month() as u8, time.day(), time.hour(), time.minute())
+}
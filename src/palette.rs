@@ -0,0 +1,114 @@
+//! Colours and an emoji for each capsule, so it's easy to tell which site a tab is on.
+
+use egui::ecolor::Hsva;
+
+use crate::theme;
+
+const EMOJI: [&str; 40] = [
+    "\u{1F30A}", "\u{1F332}", "\u{1F335}", "\u{1F337}", "\u{1F33B}", "\u{1F344}", "\u{1F34A}", "\u{1F34B}",
+    "\u{1F352}", "\u{1F353}", "\u{1F36A}", "\u{1F381}", "\u{1F388}", "\u{1F3A8}", "\u{1F3B2}", "\u{1F3B8}",
+    "\u{1F3D4}", "\u{1F3E0}", "\u{1F41A}", "\u{1F41D}", "\u{1F422}", "\u{1F427}", "\u{1F431}", "\u{1F436}",
+    "\u{1F43B}", "\u{1F451}", "\u{1F48E}", "\u{1F4A1}", "\u{1F4DA}", "\u{1F4EE}", "\u{1F52D}", "\u{1F680}",
+    "\u{1F319}", "\u{1F31E}", "\u{1F308}", "\u{1F340}", "\u{1F98A}", "\u{1F989}", "\u{1F99C}", "\u{2615}",
+];
+
+/// What a capsule's pages look like on top of the chosen theme.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    /// Accent hue, from 0 to 1.
+    pub hue: f32,
+    pub emoji: String,
+}
+
+/// Stable across runs and platforms, unlike the standard library's hasher.
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+impl Palette {
+    /// The palette for `host`, always the same for the same host.
+    pub fn generate(host: &str) -> Palette {
+        let hash = fnv1a(&host.to_ascii_lowercase());
+
+        // the top 24 bits for the hue, which is as many as an f32 holds exactly, the bottom ones for the emoji
+        Palette {
+            hue: (hash >> 40) as f32 / (1u64 << 24) as f32,
+            emoji: EMOJI[(hash % EMOJI.len() as u64) as usize].to_string(),
+        }
+    }
+
+    /// `base` with its accents recoloured, keeping their brightness so text stays readable.
+    pub fn apply(&self, base: &theme::Theme) -> theme::Theme {
+        let tint = |colour: egui::Color32, offset: f32, saturation: f32| {
+            let hsva = Hsva::from(colour);
+            Hsva::new((self.hue + offset).rem_euclid(1.0), saturation, hsva.v, hsva.a).into()
+        };
+
+        theme::Theme {
+            name: base.name.clone(),
+            dark: base.dark,
+            background: tint(base.background, 0.0, if base.dark { 0.3 } else { 0.06 }),
+            text: base.text,
+            headings: [
+                tint(base.headings[0], 0.0, 0.55),
+                tint(base.headings[1], 0.04, 0.45),
+                tint(base.headings[2], 0.08, 0.35),
+            ],
+            gemini_link: tint(base.gemini_link, 0.5, 0.6),
+            external_link: tint(base.external_link, 0.58, 0.45),
+            visited_link: tint(base.visited_link, 0.42, 0.35),
+            quote: tint(base.quote, 0.0, 0.2),
+            preformatted: base.preformatted,
+        }
+    }
+}
+
+/// Changes to a capsule's generated palette, set from the settings window.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Override {
+    /// Use the palette on this capsule, `None` to follow the global setting.
+    pub enabled: Option<bool>,
+    pub hue: Option<f32>,
+    pub emoji: Option<String>,
+}
+
+impl Override {
+    pub fn is_empty(&self) -> bool {
+        *self == Override::default()
+    }
+
+    /// Palette for `host` with this override applied, if it should be used at all.
+    pub fn palette(&self, host: &str, enabled: bool) -> Option<Palette> {
+        if !self.enabled.unwrap_or(enabled) {
+            return None;
+        }
+
+        let generated = Palette::generate(host);
+        Some(Palette {
+            hue: self.hue.unwrap_or(generated.hue),
+            emoji: self.emoji.clone().filter(|e| !e.trim().is_empty()).unwrap_or(generated.emoji),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes_are_stable() {
+        let palette = Palette::generate("example.org");
+        assert_eq!(palette, Palette::generate("EXAMPLE.org"));
+        assert_ne!(palette, Palette::generate("example.com"));
+
+        // changing how palettes are made changes every capsule's colours, so it should be deliberate
+        assert_eq!(fnv1a("example.org"), 0xee71_6063_1269_bf51);
+        assert!((palette.hue - 0.931_417_5).abs() < 1e-6);
+        assert_eq!(palette.emoji, "\u{1F332}");
+
+        let theme = palette.apply(&theme::Theme::light());
+        assert_eq!(theme.text, theme::Theme::light().text);
+        assert!((Hsva::from(theme.gemini_link).v - Hsva::from(theme::Theme::light().gemini_link).v).abs() < 0.02);
+    }
+}
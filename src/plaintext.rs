@@ -0,0 +1,143 @@
+//! Plain text documents, like RFCs and READMEs, shown as they were written.
+
+use std::ops::Range;
+
+/// Columns between tab stops.
+const TAB_WIDTH: usize = 8;
+
+/// Schemes of urls that are turned into links.
+const SCHEMES: [&str; 6] = ["gemini", "gopher", "http", "https", "finger", "spartan"];
+
+#[derive(Debug, PartialEq)]
+pub struct Line {
+    /// Line number in the original text, from 1.
+    pub number: usize,
+    /// Text with tabs expanded to spaces.
+    pub text: String,
+    /// Byte ranges of urls in the text.
+    pub links: Vec<Range<usize>>,
+    /// Comes after a form feed, which starts a new page.
+    pub page_break: bool,
+}
+
+impl Line {
+    /// The url at `offset` in the text, if there is one.
+    pub fn link_at(&self, offset: usize) -> Option<&str> {
+        self.links.iter().find(|l| l.contains(&offset)).map(|l| &self.text[l.clone()])
+    }
+}
+
+/// Split `text` into lines, expanding tabs and finding urls.
+///
+/// A form feed starts a new page, anything after it on the same line starts a new line.
+pub fn lines(text: &str) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut page_break = false;
+
+    for (i, line) in text.lines().enumerate() {
+        for (j, part) in line.split('\x0c').enumerate() {
+            page_break |= j > 0;
+            // form feeds usually have a line to themselves, which doesn't need showing
+            if part.is_empty() && line.contains('\x0c') {
+                continue;
+            }
+
+            let text = expand_tabs(part);
+            lines.push(Line {
+                number: i + 1,
+                links: find_urls(&text),
+                text,
+                page_break: std::mem::take(&mut page_break),
+            });
+        }
+    }
+
+    lines
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - column % TAB_WIDTH;
+            out.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            out.push(c);
+            column += 1;
+        }
+    }
+    out
+}
+
+/// Byte ranges of urls with one of [`SCHEMES`] in `text`.
+pub fn find_urls(text: &str) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut from = 0;
+
+    while let Some(i) = text[from..].find("://").map(|i| from + i) {
+        from = i + 3;
+
+        let start = text[..i].char_indices()
+            .rev()
+            .find(|(_, c)| !(c.is_ascii_alphanumeric() || "+.-".contains(*c)))
+            .map_or(0, |(s, c)| s + c.len_utf8());
+        if !SCHEMES.iter().any(|s| text[start..i].eq_ignore_ascii_case(s)) {
+            continue;
+        }
+
+        let end = text[from..].find(|c: char| c.is_whitespace() || "<>\"`\u{201C}\u{201D}".contains(c)).map_or(text.len(), |e| from + e);
+        let mut url = &text[start..end];
+        // punctuation after a url is more likely part of the sentence
+        loop {
+            let trimmed = url.trim_end_matches(['.', ',', ';', ':', '!', '?', '\'']);
+            let trimmed = match trimmed.chars().last() {
+                Some(close @ (')' | ']')) => {
+                    let open = if close == ')' { '(' } else { '[' };
+                    let unbalanced = trimmed.matches(open).count() < trimmed.matches(close).count();
+                    if unbalanced { &trimmed[..trimmed.len() - 1] } else { trimmed }
+                },
+                _ => trimmed,
+            };
+            if trimmed.len() == url.len() {
+                break;
+            }
+            url = trimmed;
+        }
+
+        if url.len() > text[start..i].len() + 3 {
+            urls.push(start..start + url.len());
+        }
+        from = end.max(from);
+    }
+
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_text_keeps_its_layout() {
+        let text = "Name\tValue\n1234567\tx\n\x0c\nPage 2\x0cPage 3\n";
+        let lines = lines(text);
+        let texts: Vec<_> = lines.iter().map(|l| (l.number, l.text.as_str(), l.page_break)).collect();
+        assert_eq!(texts, [
+            (1, "Name    Value", false),
+            (2, "1234567 x", false),
+            (4, "Page 2", true),
+            (4, "Page 3", true),
+        ]);
+
+        let line = "See gemini://example.org/a_(b). Or (https://example.com/x), not ftp://example.net or mailto:a@b.";
+        let urls: Vec<_> = find_urls(line).into_iter().map(|r| &line[r]).collect();
+        assert_eq!(urls, ["gemini://example.org/a_(b)", "https://example.com/x"]);
+        assert!(find_urls("a bare gemini:// scheme").is_empty());
+
+        let line = "\u{201C}https://example.com\u{201D} \u{e9}http://example.org";
+        let urls: Vec<_> = find_urls(line).into_iter().map(|r| &line[r]).collect();
+        assert_eq!(urls, ["https://example.com", "http://example.org"]);
+    }
+}
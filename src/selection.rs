@@ -0,0 +1,323 @@
+//! Selecting text across every line of a page, rather than one label at a time.
+
+use std::{ops::Range, sync::Arc};
+
+/// A point in a document, as a byte offset into the text of a line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub offset: usize,
+}
+
+impl From<usize> for Position {
+    /// The start of a line.
+    fn from(line: usize) -> Self {
+        Position { line, offset: 0 }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Selection {
+    /// Where the selection was started.
+    anchor: Position,
+    /// Where the selection was dragged to.
+    head: Position,
+    dragging: bool,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.head
+    }
+
+    /// Start and end of the selection, in document order.
+    pub fn range(&self) -> (Position, Position) {
+        (self.anchor.min(self.head), self.anchor.max(self.head))
+    }
+
+    /// Byte range of `line` that is selected, `len` is the length of its text.
+    pub fn on_line(&self, line: usize, len: usize) -> Option<Range<usize>> {
+        let (start, end) = self.range();
+        if self.is_empty() || line < start.line || line > end.line {
+            return None;
+        }
+
+        let from = if line == start.line { start.offset.min(len) } else { 0 };
+        let to = if line == end.line { end.offset.min(len) } else { len };
+        Some(from..to)
+    }
+
+    /// Select from the start of the first line to the end of `last`, whose text is `len` bytes long.
+    pub fn select_all(&mut self, last: usize, len: usize) {
+        self.anchor = Position::default();
+        self.head = Position { line: last, offset: len };
+    }
+
+    /// Follow the pointer over the text laid out this frame.
+    pub fn update(&mut self, ui: &egui::Ui, texts: &[Text]) {
+        let (press_origin, pointer, down) = ui.input(|i| (i.pointer.press_origin(), i.pointer.interact_pos(), i.pointer.primary_down()));
+
+        if texts.iter().any(|t| t.response.drag_started_by(egui::PointerButton::Primary)) {
+            if let Some(origin) = press_origin.and_then(|p| position(texts, p)) {
+                self.anchor = origin;
+                self.head = origin;
+                self.dragging = true;
+            }
+        } else if texts.iter().any(|t| t.response.clicked()) {
+            self.head = self.anchor;
+        }
+
+        if self.dragging {
+            if let Some(head) = pointer.and_then(|p| position(texts, p)) {
+                self.head = head;
+            }
+            self.dragging = down;
+        }
+    }
+
+    /// Paint the selection behind the text laid out this frame.
+    pub fn paint(&self, ui: &egui::Ui, texts: &[Text]) {
+        for text in texts {
+            let Some(range) = self.on_line(text.line, text.start + text.len()) else { continue; };
+            let range = range.start.max(text.start) - text.start..range.end.max(text.start) - text.start;
+            if range.is_empty() {
+                continue;
+            }
+
+            if let Some(order) = &text.order {
+                text.painter.set(text.background, egui::Shape::Vec(text.reordered_rects(ui, order, range)));
+                continue;
+            }
+
+            let galley = &text.galley;
+            let start = galley.from_ccursor(egui::text::CCursor::new(text.char_index(range.start)));
+            let end = galley.from_ccursor(egui::text::CCursor::new(text.char_index(range.end)));
+
+            let rects = galley.rows.iter()
+                .enumerate()
+                .take(end.rcursor.row + 1)
+                .skip(start.rcursor.row)
+                .map(|(r, row)| {
+                    let left = if r == start.rcursor.row { galley.pos_from_cursor(&start).left() } else { row.rect.left() };
+                    let right = if r == end.rcursor.row { galley.pos_from_cursor(&end).left() } else { row.rect.right() };
+                    let rect = egui::Rect::from_x_y_ranges(left..=right, row.rect.y_range()).translate(text.pos.to_vec2());
+                    egui::Shape::rect_filled(rect, 0.0, ui.visuals().selection.bg_fill)
+                })
+                .collect();
+
+            text.painter.set(text.background, egui::Shape::Vec(rects));
+        }
+    }
+}
+
+/// Where the text under `pos` is, or the nearest text to it.
+fn position(texts: &[Text], pos: egui::Pos2) -> Option<Position> {
+    let text = texts.iter().min_by(|a, b| {
+        a.rect().distance_sq_to_pos(pos).total_cmp(&b.rect().distance_sq_to_pos(pos))
+    })?;
+
+    Some(Position {
+        line: text.line,
+        offset: text.start + text.galley_offset(pos).saturating_sub(text.prefix).min(text.len()),
+    })
+}
+
+/// The text of a line as laid out this frame.
+pub struct Text {
+    line: usize,
+    /// Byte offset into the line's text where this text starts, a line can be laid out in parts like table cells.
+    start: usize,
+    pos: egui::Pos2,
+    galley: Arc<egui::Galley>,
+    /// Bytes at the start of the galley that aren't part of the line's text, like a bullet.
+    prefix: usize,
+    response: egui::Response,
+    painter: egui::Painter,
+    /// Placeholder for the selection, so it is painted beneath the text.
+    background: egui::layers::ShapeIdx,
+    /// Where each char of a reordered galley came from, see [`Job::order`].
+    order: Option<Vec<(usize, bool)>>,
+}
+
+impl Text {
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn response(&self) -> &egui::Response {
+        &self.response
+    }
+
+    /// Byte offset into the line's text at `pos`, if it is over the text rather than anything before it.
+    pub fn offset_at(&self, pos: egui::Pos2) -> Option<usize> {
+        self.galley_offset(pos).checked_sub(self.prefix).map(|offset| self.start + offset)
+    }
+
+    /// Top left of the char at byte `offset` of the line's text, if it's in this text.
+    pub fn pos_of(&self, offset: usize) -> Option<egui::Pos2> {
+        let offset = offset.checked_sub(self.start).filter(|o| *o < self.len())? + self.prefix;
+        let rect = match &self.order {
+            Some(order) => {
+                let mut index = 0;
+                let mut found = None;
+                for row in &self.galley.rows {
+                    for glyph in &row.glyphs {
+                        if order[index].0 == offset {
+                            found = Some(egui::Rect::from_x_y_ranges(glyph.pos.x..=glyph.max_x(), row.rect.y_range()));
+                        }
+                        index += 1;
+                    }
+                    index += row.ends_with_newline as usize;
+                }
+                found?
+            },
+            None => {
+                let index = self.galley.text()[..offset].chars().count();
+                self.galley.pos_from_cursor(&self.galley.from_ccursor(egui::text::CCursor::new(index)))
+            },
+        };
+        Some(rect.left_top() + self.pos.to_vec2())
+    }
+
+    /// Byte offset into the galley's (unordered) text at `pos`.
+    fn galley_offset(&self, pos: egui::Pos2) -> usize {
+        match &self.order {
+            Some(order) => self.reordered_offset(order, pos),
+            None => {
+                let cursor = self.galley.cursor_from_pos(pos - self.pos);
+                self.galley.text().char_indices()
+                    .nth(cursor.ccursor.index)
+                    .map_or(self.galley.text().len(), |(b, _)| b)
+            },
+        }
+    }
+
+    fn rect(&self) -> egui::Rect {
+        self.galley.rect.translate(self.pos.to_vec2())
+    }
+
+    fn len(&self) -> usize {
+        match &self.order {
+            Some(order) => order[order.len() - 1].0 - self.prefix,
+            None => self.galley.text().len() - self.prefix,
+        }
+    }
+
+    /// Rectangles behind the chars of a reordered galley in `range`, which may not be next to each other.
+    fn reordered_rects(&self, ui: &egui::Ui, order: &[(usize, bool)], range: Range<usize>) -> Vec<egui::Shape> {
+        let range = self.prefix + range.start..self.prefix + range.end;
+        let mut rects: Vec<egui::Rect> = Vec::new();
+
+        let mut index = 0;
+        for row in &self.galley.rows {
+            let mut last: Option<egui::Rect> = None;
+            for glyph in &row.glyphs {
+                if range.contains(&order[index].0) {
+                    let rect = egui::Rect::from_x_y_ranges(glyph.pos.x..=glyph.max_x(), row.rect.y_range());
+                    last = Some(last.map_or(rect, |last| last.union(rect)));
+                } else if let Some(last) = last.take() {
+                    rects.push(last);
+                }
+                index += 1;
+            }
+            rects.extend(last);
+            index += row.ends_with_newline as usize;
+        }
+
+        rects.into_iter()
+            .map(|rect| egui::Shape::rect_filled(rect.translate(self.pos.to_vec2()), 0.0, ui.visuals().selection.bg_fill))
+            .collect()
+    }
+
+    /// Byte offset of the galley text at `pos` in a reordered galley.
+    ///
+    /// The side of a char that comes first depends on its direction, so this
+    /// can't go through the galley's own cursors.
+    fn reordered_offset(&self, order: &[(usize, bool)], pos: egui::Pos2) -> usize {
+        let pos = pos - self.pos.to_vec2();
+        let rows = &self.galley.rows;
+        let row = rows.iter().position(|r| pos.y < r.rect.bottom()).unwrap_or(rows.len() - 1);
+        let start: usize = rows[..row].iter().map(|r| r.glyphs.len() + r.ends_with_newline as usize).sum();
+
+        let glyphs = &rows[row].glyphs;
+        let distance = |g: &egui::epaint::text::Glyph| (pos.x - g.logical_rect().center().x).abs();
+        let Some(g) = (0..glyphs.len()).min_by(|a, b| distance(&glyphs[*a]).total_cmp(&distance(&glyphs[*b]))) else {
+            return order[start].0;
+        };
+
+        let (byte, rtl) = order[start + g];
+        let after = pos.x > glyphs[g].logical_rect().center().x;
+        if after != rtl { byte + glyphs[g].chr.len_utf8() } else { byte }
+    }
+
+    /// Char index in the galley of a byte offset into the line's text.
+    fn char_index(&self, offset: usize) -> usize {
+        self.galley.text()[..self.prefix + offset].chars().count()
+    }
+}
+
+/// Text to lay out for a line, which may have been reordered for display.
+pub struct Job {
+    pub job: egui::text::LayoutJob,
+    /// For text reordered by [`crate::bidi::reorder`], the byte offset of each char
+    /// in the original text and whether it reads right to left, then the original length.
+    /// Already split into rows.
+    pub order: Option<Vec<(usize, bool)>>,
+}
+
+impl From<egui::text::LayoutJob> for Job {
+    fn from(job: egui::text::LayoutJob) -> Self {
+        Job { job, order: None }
+    }
+}
+
+/// Lay out and paint `job` in `colour` like a label, or a link if `link` is set.
+///
+/// The line's text (`text_len` bytes) must be at the end of `job`, anything
+/// before it can't be selected. `at` is where in the document the text starts,
+/// usually the start of a line.
+pub fn label(ui: &mut egui::Ui, texts: &mut Vec<Text>, at: impl Into<Position>, text_len: usize, job: impl Into<Job>, colour: egui::Color32, link: bool) -> egui::Response {
+    let Position { line, offset: start } = at.into();
+    let Job { job, order } = job.into();
+    let background = ui.painter().add(egui::Shape::Noop);
+
+    let mut label = egui::Label::new(job)
+        .selectable(false)
+        .sense(egui::Sense::click_and_drag());
+    if order.is_some() {
+        label = label.wrap(false);
+    }
+    let (pos, galley, response) = label.layout_in_ui(ui);
+
+    let kind = if link { egui::WidgetType::Link } else { egui::WidgetType::Label };
+    response.widget_info(|| egui::WidgetInfo::labeled(kind, galley.text()));
+
+    if ui.is_rect_visible(response.rect) {
+        let underline = if link && (response.hovered() || response.has_focus()) {
+            egui::Stroke::new(ui.style().interact(&response).fg_stroke.width, colour)
+        } else {
+            egui::Stroke::NONE
+        };
+
+        ui.painter().add(egui::epaint::TextShape::new(pos, galley.clone(), colour).with_underline(underline));
+
+        if response.hovered() {
+            ui.ctx().set_cursor_icon(if link { egui::CursorIcon::PointingHand } else { egui::CursorIcon::Text });
+        }
+    }
+
+    let len = order.as_ref().map_or(galley.text().len(), |o| o[o.len() - 1].0);
+    texts.push(Text {
+        line,
+        start,
+        pos,
+        prefix: len - text_len,
+        galley,
+        response: response.clone(),
+        painter: ui.painter().clone(),
+        background,
+        order,
+    });
+
+    response
+}
@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+
+use crate::{downloads, handlers, hints, palette, theme};
+
+/// User preferences, persisted between sessions.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub theme: theme::ThemeChoice,
+    /// Give each capsule its own accent colours and emoji, see [`palette::Palette`].
+    pub capsule_palettes: bool,
+    /// Capsules whose palette has been changed, by host.
+    pub capsule_palette_hosts: BTreeMap<String, palette::Override>,
+    /// Render ANSI colour escapes in preformatted blocks, otherwise they are stripped.
+    pub ansi_colours: bool,
+    /// Show preformatted blocks as just their alt text until expanded.
+    pub collapse_preformatted: bool,
+    /// Soft wrap plain text documents, instead of scrolling sideways.
+    pub plain_text_wrap: bool,
+    pub plain_text_line_numbers: bool,
+    /// Label links for keyboard navigation.
+    pub link_hints: hints::LinkHints,
+    /// Fetch images from image links and show them under the link.
+    pub inline_images: bool,
+    /// Capsules where `inline_images` is overridden, by host.
+    pub inline_images_hosts: BTreeMap<String, bool>,
+    /// Folder downloads are saved to, empty for the system's downloads folder.
+    pub download_directory: String,
+    /// Programs for link schemes and mimetypes Vostok can't open itself.
+    pub handlers: Vec<handlers::Handler>,
+    pub typography: Typography,
+}
+
+impl Settings {
+    pub const STORAGE_KEY: &'static str = "settings";
+
+    pub fn inline_images_for(&self, host: &str) -> bool {
+        self.inline_images_hosts.get(host).copied().unwrap_or(self.inline_images)
+    }
+
+    pub fn palette_for(&self, host: &str) -> Option<palette::Palette> {
+        self.capsule_palette_hosts.get(host).cloned().unwrap_or_default().palette(host, self.capsule_palettes)
+    }
+
+    /// `host` is the capsule being shown, for settings that can be changed per capsule.
+    pub fn ui(&mut self, ui: &mut egui::Ui, host: Option<&str>) {
+        ui.horizontal(|ui| {
+            let selected = match &self.theme {
+                theme::ThemeChoice::System => "Follow system",
+                theme::ThemeChoice::Named(name) => name,
+            };
+            egui::ComboBox::from_label("Theme").selected_text(selected).show_ui(ui, |ui| {
+                ui.selectable_value(&mut self.theme, theme::ThemeChoice::System, "Follow system");
+                for name in theme::names() {
+                    ui.selectable_value(&mut self.theme, theme::ThemeChoice::Named(name.clone()), name);
+                }
+            });
+            let directory = theme::directory().map_or_else(|| "the themes folder".into(), |d| d.display().to_string());
+            if ui.button("Reload").on_hover_text(format!("Read theme files from {directory} again")).clicked() {
+                theme::load();
+            }
+        });
+        ui.checkbox(&mut self.capsule_palettes, "Give each capsule its own colours and emoji");
+        if let Some(host) = host {
+            let mut choice = self.capsule_palette_hosts.get(host).cloned().unwrap_or_default();
+            let generated = palette::Palette::generate(host);
+            ui.horizontal(|ui| {
+                ui.label(format!("Colours on {host}"));
+                ui.selectable_value(&mut choice.enabled, None, "Default");
+                ui.selectable_value(&mut choice.enabled, Some(true), "Show");
+                ui.selectable_value(&mut choice.enabled, Some(false), "Don't show");
+            });
+            if choice.enabled.unwrap_or(self.capsule_palettes) {
+                ui.horizontal(|ui| {
+                    let mut hue = choice.hue.unwrap_or(generated.hue);
+                    if ui.add(egui::Slider::new(&mut hue, 0.0..=1.0).show_value(false).text("Hue")).changed() {
+                        choice.hue = Some(hue);
+                    }
+                    let mut emoji = choice.emoji.clone().unwrap_or(generated.emoji.clone());
+                    if ui.add(egui::TextEdit::singleline(&mut emoji).desired_width(32.0)).changed() {
+                        choice.emoji = Some(emoji);
+                    }
+                    if ui.add_enabled(choice.hue.is_some() || choice.emoji.is_some(), egui::Button::new("Reset")).clicked() {
+                        choice.hue = None;
+                        choice.emoji = None;
+                    }
+                });
+            }
+            if choice.is_empty() {
+                self.capsule_palette_hosts.remove(host);
+            } else {
+                self.capsule_palette_hosts.insert(host.into(), choice);
+            }
+        }
+        ui.checkbox(&mut self.ansi_colours, "Show ANSI colours in preformatted text");
+        ui.checkbox(&mut self.collapse_preformatted, "Collapse preformatted text to its alt text");
+        ui.checkbox(&mut self.plain_text_wrap, "Wrap plain text");
+        ui.checkbox(&mut self.plain_text_line_numbers, "Show line numbers in plain text");
+        ui.horizontal(|ui| {
+            ui.label("Link hints");
+            ui.selectable_value(&mut self.link_hints, hints::LinkHints::Off, "Off");
+            ui.selectable_value(&mut self.link_hints, hints::LinkHints::Numbers, "Numbers");
+            ui.selectable_value(&mut self.link_hints, hints::LinkHints::Letters, "Letters");
+        }).response.on_hover_text("Type a link's hint to follow it, hold shift to open it in a new tab");
+        ui.checkbox(&mut self.inline_images, "Show images from image links under the link");
+        if let Some(host) = host {
+            ui.horizontal(|ui| {
+                ui.label(format!("Images on {host}"));
+                let mut choice = self.inline_images_hosts.get(host).copied();
+                ui.selectable_value(&mut choice, None, "Default");
+                ui.selectable_value(&mut choice, Some(true), "Show");
+                ui.selectable_value(&mut choice, Some(false), "Don't show");
+                match choice {
+                    Some(show) => self.inline_images_hosts.insert(host.into(), show),
+                    None => self.inline_images_hosts.remove(host),
+                };
+            });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Download folder");
+            let default = downloads::default_directory().map(|d| d.display().to_string()).unwrap_or_default();
+            ui.add(egui::TextEdit::singleline(&mut self.download_directory).hint_text(default));
+        });
+        ui.collapsing("Reading", |ui| self.typography.ui(ui));
+        ui.collapsing("External handlers", |ui| handlers::ui(ui, &mut self.handlers));
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            theme: theme::ThemeChoice::default(),
+            capsule_palettes: true,
+            capsule_palette_hosts: BTreeMap::new(),
+            ansi_colours: true,
+            collapse_preformatted: false,
+            plain_text_wrap: true,
+            plain_text_line_numbers: false,
+            link_hints: hints::LinkHints::Off,
+            inline_images: false,
+            inline_images_hosts: BTreeMap::new(),
+            download_directory: String::new(),
+            handlers: Vec::new(),
+            typography: Typography::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum BodyFont {
+    #[default]
+    Proportional,
+    Serif,
+    Monospace,
+}
+
+/// How page text is laid out.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct Typography {
+    /// Widest the page's column gets, in points.
+    pub content_width: f32,
+    pub font_size: f32,
+    /// Height of a row of text, as a multiple of the font's own.
+    pub line_spacing: f32,
+    /// Space between lines of gemtext, in points.
+    pub paragraph_spacing: f32,
+    /// Size of a level one heading relative to body text, lower levels are scaled less.
+    pub heading_scale: f32,
+    pub body_font: BodyFont,
+}
+
+impl Typography {
+    /// Font family of body text and headings.
+    pub fn family(&self) -> egui::FontFamily {
+        match self.body_font {
+            BodyFont::Proportional => egui::FontFamily::Proportional,
+            BodyFont::Serif => egui::FontFamily::Name("serif".into()),
+            BodyFont::Monospace => egui::FontFamily::Monospace,
+        }
+    }
+
+    pub fn body_font(&self) -> egui::FontId {
+        egui::FontId::new(self.font_size, self.family())
+    }
+
+    pub fn heading_font(&self, level: u8) -> egui::FontId {
+        egui::FontId::new(self.font_size * (1.0 + (self.heading_scale - 1.0) / level as f32), self.family())
+    }
+
+    /// Font for preformatted text, which is always monospace.
+    pub fn preformatted_font(&self) -> egui::FontId {
+        egui::FontId::monospace(self.font_size)
+    }
+
+    /// Row height for text in `font_id`, `None` for the font's own.
+    pub fn line_height(&self, ui: &egui::Ui, font_id: &egui::FontId) -> Option<f32> {
+        (self.line_spacing != 1.0).then(|| ui.fonts(|f| f.row_height(font_id)) * self.line_spacing)
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.content_width, 400.0..=2000.0).step_by(10.0).suffix(" pt").text("Content width"));
+        ui.add(egui::Slider::new(&mut self.font_size, 8.0..=32.0).step_by(0.5).suffix(" pt").text("Font size"));
+        ui.add(egui::Slider::new(&mut self.line_spacing, 1.0..=2.5).step_by(0.05).text("Line spacing"));
+        ui.add(egui::Slider::new(&mut self.paragraph_spacing, 0.0..=32.0).step_by(1.0).suffix(" pt").text("Paragraph spacing"));
+        ui.add(egui::Slider::new(&mut self.heading_scale, 1.0..=3.0).step_by(0.05).text("Heading scale"));
+        ui.horizontal(|ui| {
+            ui.label("Font");
+            ui.selectable_value(&mut self.body_font, BodyFont::Proportional, "Sans serif");
+            ui.selectable_value(&mut self.body_font, BodyFont::Serif, "Serif");
+            ui.selectable_value(&mut self.body_font, BodyFont::Monospace, "Monospace");
+        });
+        if ui.button("Reset").clicked() {
+            *self = Typography::default();
+        }
+    }
+}
+
+impl Default for Typography {
+    fn default() -> Self {
+        Self {
+            content_width: 800.0,
+            font_size: 12.5,
+            line_spacing: 1.0,
+            paragraph_spacing: 3.0,
+            heading_scale: 2.0,
+            body_font: BodyFont::Proportional,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! Page source, the response shown exactly as the server sent it.
+
+use crate::{gemini, gemtext::GemLine, plaintext};
+
+pub const SCHEME: &str = "view-source";
+
+/// Most bytes of a binary body shown in the hex dump.
+const MAX_DUMP: usize = 64 * 1024;
+
+/// What a line of source is, for highlighting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// The status line.
+    Header,
+    Text,
+    Heading(u8),
+    Link,
+    ListItem,
+    Quote,
+    /// A line that starts or ends a preformatted block.
+    Toggle,
+    Preformatted,
+    /// A row of the hex dump of a body that isn't text.
+    Binary,
+}
+
+/// The `view-source:` url for `page`.
+pub fn url(page: &url::Url) -> Option<url::Url> {
+    url::Url::parse(&format!("{SCHEME}:{page}")).ok()
+}
+
+/// The page a `view-source:` url shows the source of.
+pub fn page_url(url: &url::Url) -> Option<url::Url> {
+    // everything after the scheme, as the query and fragment are the page's
+    url.as_str().strip_prefix(SCHEME)?.strip_prefix(':').and_then(|page| url::Url::parse(page).ok())
+}
+
+/// `line` with its control characters, like tabs and carriage returns, swapped for symbols that show them.
+fn visible(line: &str) -> String {
+    line.chars()
+        .map(|c| match c {
+            '\0'..='\x1f' => char::from_u32(0x2400 + c as u32).unwrap_or(c),
+            '\x7f' => '\u{2421}',
+            c => c,
+        })
+        .collect()
+}
+
+/// A line of source, numbered from 1.
+fn line(number: usize, text: &str) -> plaintext::Line {
+    let text = visible(text);
+    plaintext::Line {
+        number,
+        links: plaintext::find_urls(&text),
+        text,
+        page_break: false,
+    }
+}
+
+/// The status line then the body, gemtext is highlighted and anything that isn't text is hex dumped.
+///
+/// Text is split only at newlines and otherwise shown as it was sent.
+pub fn lines(raw: &gemini::RawResponse) -> Vec<GemLine> {
+    let header = String::from_utf8_lossy(&raw.header);
+    let mut lines = vec![GemLine::Source(line(1, header.strip_suffix('\n').unwrap_or(&header)), Kind::Header)];
+
+    let mimetype = raw.meta.split(';').next().unwrap_or_default().trim();
+    let is_text = mimetype.starts_with("text/") || std::str::from_utf8(&raw.body).is_ok();
+    if !is_text {
+        lines.extend(hex_dump(&raw.body));
+        return lines;
+    }
+
+    if raw.body.is_empty() {
+        return lines;
+    }
+
+    let gemtext = raw.status / 10 == 2 && mimetype == "text/gemini";
+    let mut preformatted = false;
+    let body = String::from_utf8_lossy(&raw.body);
+    // a newline ends a line rather than starting an empty one
+    let body = body.strip_suffix('\n').unwrap_or(&body);
+    for (i, text) in body.split('\n').enumerate() {
+        let kind = if !gemtext {
+            Kind::Text
+        } else if text.starts_with("```") {
+            preformatted = !preformatted;
+            Kind::Toggle
+        } else if preformatted {
+            Kind::Preformatted
+        } else {
+            kind(text)
+        };
+        lines.push(GemLine::Source(line(i + 2, text), kind));
+    }
+
+    lines
+}
+
+/// The type of a gemtext line outside of a preformatted block.
+fn kind(line: &str) -> Kind {
+    if line.starts_with("=>") {
+        Kind::Link
+    } else if line.starts_with('#') {
+        Kind::Heading(line.bytes().take_while(|b| *b == b'#').count().min(3) as u8)
+    } else if line.starts_with("* ") {
+        Kind::ListItem
+    } else if line.starts_with('>') {
+        Kind::Quote
+    } else {
+        Kind::Text
+    }
+}
+
+/// Rows of sixteen bytes, in hex and as ASCII.
+fn hex_dump(body: &[u8]) -> Vec<GemLine> {
+    let mut lines: Vec<GemLine> = body[..body.len().min(MAX_DUMP)].chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let hex: Vec<String> = row.iter().map(|b| format!("{b:02x}")).collect();
+            let ascii: String = row.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }).collect();
+            GemLine::Source(plaintext::Line {
+                number: i + 2,
+                text: format!("{:08x}  {:<47}  {ascii}", i * 16, hex.join(" ")),
+                links: Vec::new(),
+                page_break: false,
+            }, Kind::Binary)
+        })
+        .collect();
+
+    if body.len() > MAX_DUMP {
+        lines.push(GemLine::Source(plaintext::Line {
+            number: lines.len() + 2,
+            text: format!("\u{2026} {} more bytes", body.len() - MAX_DUMP),
+            links: Vec::new(),
+            page_break: false,
+        }, Kind::Header));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_is_highlighted_by_line_type() {
+        let raw = gemini::RawResponse {
+            status: 20,
+            meta: "text/gemini; lang=en".into(),
+            header: b"20 text/gemini; lang=en\r\n".to_vec(),
+            body: b"# Title\n=> /a Link\n```\n# not a heading\n```\n> quote\n".as_slice().into(),
+        };
+        let kinds: Vec<_> = lines(&raw).into_iter().map(|l| match l {
+            GemLine::Source(line, kind) => (line.number, kind),
+            _ => panic!("expected source lines"),
+        }).collect();
+        assert_eq!(kinds, [
+            (1, Kind::Header),
+            (2, Kind::Heading(1)),
+            (3, Kind::Link),
+            (4, Kind::Toggle),
+            (5, Kind::Preformatted),
+            (6, Kind::Toggle),
+            (7, Kind::Quote),
+        ]);
+
+        let text = gemini::RawResponse {
+            status: 20,
+            meta: "text/plain".into(),
+            header: b"20  text/plain\r\n".to_vec(),
+            body: b"a\tb\r\n\x0cpage\n\nend".as_slice().into(),
+        };
+        let text: Vec<String> = lines(&text).iter().map(|l| l.text().to_owned()).collect();
+        assert_eq!(text, ["20  text/plain\u{240D}", "a\u{2409}b\u{240D}", "\u{240C}page", "", "end"]);
+
+        let quoted = gemini::RawResponse {
+            status: 20,
+            meta: "text/gemini".into(),
+            header: b"20 text/gemini\r\n".to_vec(),
+            body: "Caf\u{e9}gemini://example.org/ \u{201C}https://example.com\u{201D}\n".as_bytes().into(),
+        };
+        let GemLine::Source(line, _) = &lines(&quoted)[1] else { panic!("expected a source line") };
+        let urls: Vec<_> = line.links.iter().map(|l| &line.text[l.clone()]).collect();
+        assert_eq!(urls, ["gemini://example.org/", "https://example.com"]);
+
+        let binary = gemini::RawResponse { status: 20, meta: "image/png".into(), header: Vec::new(), body: [0x89, b'P', b'N', b'G', 0xff].as_slice().into() };
+        assert_eq!(lines(&binary)[1].text(), "00000000  89 50 4e 47 ff                                   .PNG.");
+
+        let page = url::Url::parse("gemini://example.org/search?q#top").unwrap();
+        assert_eq!(page_url(&url(&page).unwrap()), Some(page));
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::OnceLock;
+
+use syntect::{easy::HighlightLines, highlighting::{FontStyle, ThemeSet}, parsing::{SyntaxReference, SyntaxSet}, util::LinesWithEndings};
+
+const DARK_THEME: &str = "base16-ocean.dark";
+const LIGHT_THEME: &str = "InspiredGitHub";
+
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn themes() -> &'static ThemeSet {
+    THEMES.get_or_init(ThemeSet::load_defaults)
+}
+
+/// Find the language named by a preformatted block's alt text, e.g. `rust`, `sh` or `python`.
+///
+/// Only the first word is considered, so alt text like `rust example` still matches.
+pub fn syntax_for(alt: &str) -> Option<&'static SyntaxReference> {
+    let token = alt.split_whitespace().next()?;
+    let syntax = syntaxes().find_syntax_by_token(token)?;
+
+    // plain text would be highlighted in a single colour anyway
+    (syntax.name != "Plain Text").then_some(syntax)
+}
+
+#[derive(Default)]
+struct Highlighter;
+
+impl egui::util::cache::ComputerMut<(&str, &str, bool, &egui::FontId), egui::text::LayoutJob> for Highlighter {
+    fn compute(&mut self, (code, syntax, dark_mode, font_id): (&str, &str, bool, &egui::FontId)) -> egui::text::LayoutJob {
+        let mut job = egui::text::LayoutJob::default();
+
+        let syntax = syntaxes().find_syntax_by_name(syntax).expect("syntax names come from syntax_for");
+        let theme = &themes().themes[if dark_mode { DARK_THEME } else { LIGHT_THEME }];
+
+        let mut highlighter = HighlightLines::new(syntax, theme);
+
+        for line in LinesWithEndings::from(code) {
+            let Ok(ranges) = highlighter.highlight_line(line, syntaxes()) else {
+                // give up on the rest of the block, but still show it
+                job.append(&code[job.text.len()..], 0.0, egui::TextFormat::simple(font_id.clone(), egui::Color32::PLACEHOLDER));
+                break;
+            };
+
+            for (style, text) in ranges {
+                let fg = style.foreground;
+                job.append(text, 0.0, egui::TextFormat {
+                    font_id: font_id.clone(),
+                    color: egui::Color32::from_rgba_unmultiplied(fg.r, fg.g, fg.b, fg.a),
+                    italics: style.font_style.contains(FontStyle::ITALIC),
+                    underline: if style.font_style.contains(FontStyle::UNDERLINE) {
+                        egui::Stroke::new(1.0, egui::Color32::from_rgb(fg.r, fg.g, fg.b))
+                    } else {
+                        egui::Stroke::NONE
+                    },
+                    ..Default::default()
+                });
+            }
+        }
+
+        job
+    }
+}
+
+type HighlightCache = egui::util::cache::FrameCache<egui::text::LayoutJob, Highlighter>;
+
+/// Highlight `code` in the given language, with colours following the current light or dark mode.
+pub fn highlight(ui: &egui::Ui, code: &str, syntax: &SyntaxReference, font_id: &egui::FontId) -> egui::text::LayoutJob {
+    let dark_mode = ui.visuals().dark_mode;
+    ui.ctx().memory_mut(|mem| {
+        mem.caches.cache::<HighlightCache>().get((code, syntax.name.as_str(), dark_mode, font_id))
+    })
+}
@@ -1,12 +1,25 @@
-use std::{io, thread};
+use std::{io, thread, sync::{Arc, Mutex}};
 
-use crate::{gemini, gemtext};
+use crate::{bookmarks, downloads, feeds, gemini, handlers, gemtext, lint, markdown, source, viewer, visited, xmlfeed};
 
 pub enum ActionRequired {
     Input {
         prompt: String,
         sensitive: bool,
     },
+    /// Another program has to open it.
+    Launch(handlers::Launch),
+}
+
+/// A gemtext page that's still arriving, filled in by the thread reading it, see [`Tab::receive`].
+#[derive(Default)]
+struct Incoming {
+    /// Parsed since the tab last took them.
+    lines: Vec<(u64, gemtext::GemLine)>,
+    /// The response, how it arrived and the problems with it, once all of it has been read.
+    finished: Option<(gemini::RawResponse, gemini::Info, Vec<lint::Diagnostic>)>,
+    /// Why the rest of the page couldn't be read.
+    error: Option<String>,
 }
 
 pub struct Tab {
@@ -14,7 +27,17 @@ pub struct Tab {
     pub display_url: String,
     title: String,
     content: gemtext::GemText,
+    /// Problems with the page's gemtext, see [`lint::lint`].
+    diagnostics: Vec<lint::Diagnostic>,
+    /// Shown instead of the content for image responses.
+    viewer: Option<viewer::Viewer>,
+    /// The response the page was shown from, for viewing its source.
+    raw: Option<gemini::RawResponse>,
+    /// How the response arrived, for the page info.
+    info: Option<gemini::Info>,
     request_thread: Option<thread::JoinHandle<Result<Tab, ActionRequired>>>,
+    /// The rest of a page that's shown while it arrives.
+    incoming: Option<Arc<Mutex<Incoming>>>,
 }
 
 impl Tab {
@@ -26,6 +49,7 @@ impl Tab {
     const NEW_TEMPLATE: &'static str = include_str!("templates/new.gmi");
     const ERROR_TEMPLATE: &'static str = include_str!("templates/error.gmi");
     
+    /// A tab that will show `url` once loaded, see [`Tab::is_blank`].
     pub fn new(url: url::Url) -> Tab {
         let title = Self::display_url(&url);
         
@@ -33,8 +57,13 @@ impl Tab {
             url,
             display_url: title.clone(),
             title,
-            content: gemtext::GemText::new(""),
+            content: Default::default(),
+            diagnostics: Vec::new(),
+            viewer: None,
+            raw: None,
+            info: None,
             request_thread: None,
+            incoming: None,
         };
 
         tab.request(tab.url.clone());
@@ -57,37 +86,162 @@ impl Tab {
             display_url,
             title: error,
             content,
+            diagnostics: Vec::new(),
+            viewer: None,
+            raw: None,
+            info: None,
             request_thread: None,
+            incoming: None,
+        }
+    }
+
+    /// A generated `about:` page.
+    fn new_browser_page(url: &str, title: &str, content: &str) -> Tab {
+        Tab {
+            url: url::Url::parse(url).expect("browser page urls are valid"),
+            display_url: String::new(),
+            title: title.into(),
+            content: gemtext::GemText::new(content),
+            diagnostics: Vec::new(),
+            viewer: None,
+            raw: None,
+            info: None,
+            request_thread: None,
+            incoming: None,
+        }
+    }
+
+    /// The source of a page from `cached`, the response it was shown from.
+    ///
+    /// Asking for the page again could get a different response, so there's only source for pages that are open.
+    fn new_source(url: url::Url, cached: Option<gemini::RawResponse>) -> Tab {
+        let Some(page) = source::page_url(&url) else {
+            return Tab::new_error(url, 0, "Not a page's url");
+        };
+        let Some(raw) = cached else {
+            return Tab::new_error(url, 0, "The source is only kept for the page open in the tab, open the page then view its source");
+        };
+
+        Tab {
+            url,
+            display_url: String::new(),
+            title: format!("Source of {}", Self::display_url(&page)),
+            content: gemtext::GemText::view_source(&raw),
+            diagnostics: Vec::new(),
+            viewer: None,
+            raw: Some(raw),
+            info: None,
+            request_thread: None,
+            incoming: None,
         }
     }
 
     pub fn request(&mut self, mut url: url::Url) {
+        // the page's response, or the one a source page was made from when it's reloaded
+        let cached = self.raw.as_ref()
+            .filter(|_| source::page_url(&url).as_ref() == Some(&self.url) || (url == self.url && url.scheme() == source::SCHEME))
+            .cloned();
+        // any page can link to the feeds and downloads pages' actions, so they only work from the pages themselves
+        let from_feeds = self.url.as_str() == feeds::PAGE_URL;
+        let from_downloads = self.url.as_str() == downloads::PAGE_URL;
+
         self.request_thread = Some(thread::spawn(move || {
+            if url.scheme() == source::SCHEME {
+                return Ok(Tab::new_source(url, cached));
+            }
+
             if url.scheme() == Self::BROWSER_SCHEME {
                 match url.host_str().unwrap_or_default() {
                     "new" => return Ok(Default::default()),
+                    "feeds" => {
+                        if from_feeds {
+                            match url.path() {
+                                "/refresh" => feeds::refresh(),
+                                "/read" => feeds::mark_all_read(),
+                                _ => {},
+                            }
+                        }
+                        return Ok(Tab::new_browser_page(feeds::PAGE_URL, "Feeds", &feeds::page()));
+                    },
+                    "downloads" => {
+                        if from_downloads {
+                            downloads::action(url.path(), url.query());
+                        }
+                        return Ok(Tab::new_browser_page(downloads::PAGE_URL, "Downloads", &downloads::page()));
+                    },
+                    "bookmarks" => return Ok(Tab::new_browser_page(bookmarks::PAGE_URL, "Bookmarks", &bookmarks::page())),
                     host => return Ok(Tab::new_error(url.clone(), 0, format!("Unknown browser page '{host}'"))),
                 }
             }
 
+            if url.scheme() != gemini::SCHEME {
+                return Err(ActionRequired::Launch(handlers::for_url(&url)));
+            }
+
+            feeds::mark_read(&url);
+            visited::visit(url.as_str());
+
             let mut redirections = vec![url.clone()];
+            let mut raw = None;
+            let mut info = None;
 
             let mut out = Tab {
                 url: url.clone(),
                 display_url: String::new(),
                 title: Self::display_url(&url),
                 content: Default::default(),
+                diagnostics: Vec::new(),
+                viewer: None,
+                raw: None,
+                info: None,
                 request_thread: None,
+                incoming: None,
             };
         
             for _ in 0..Self::MAX_REDIRECTS {
-                let response = match gemini::request(&url) {
+                let stream = match gemini::open(&url) {
+                    Ok(stream) => stream,
+                    Err(err) => return Ok(Tab::new_error(url, 0, err.to_string())),
+                };
+
+                // anything that can't be shown is saved as it arrives rather than read into memory
+                if stream.status / 10 == 2 && !downloads::can_show(&stream.meta) {
+                    if let Some(launch) = handlers::for_mimetype(&stream.meta) {
+                        return match downloads::save_temporary(&url, stream) {
+                            Ok(path) => Err(ActionRequired::Launch(handlers::Launch { argument: path.display().to_string(), ..launch })),
+                            Err(err) => Ok(Tab::new_error(url, 0, format!("Couldn't download the file: {err}"))),
+                        };
+                    }
+                    if let Err(err) = downloads::start_stream(&url, stream) {
+                        return Ok(Tab::new_error(url, 0, format!("Couldn't download the file: {err}")));
+                    }
+                    return Ok(Tab::new_browser_page(downloads::PAGE_URL, "Downloads", &downloads::page()));
+                }
+
+                // gemtext is shown a line at a time as it arrives
+                if stream.status / 10 == 2 && Self::mimetype(&stream.meta).0 == "text/gemini" {
+                    let lang = Self::mimetype(&stream.meta).1.unwrap_or_default();
+                    out.content = gemtext::GemText::default().with_lang(lang);
+                    let incoming = Arc::new(Mutex::new(Incoming::default()));
+                    out.incoming = Some(incoming.clone());
+                    thread::spawn(move || Self::read_gemtext(url, stream, incoming));
+                    return Ok(out);
+                }
+
+                let response = match stream.into_raw() {
+                    Ok((response, response_info)) => {
+                        info = Some(response_info);
+                        raw.insert(response)
+                    },
+                    Err(err) => return Ok(Tab::new_error(url, 0, err.to_string())),
+                };
+                let response = match gemini::Response::try_from(&*response) {
                     Ok(response) => response,
                     Err(err) => return Ok(Tab::new_error(url, 0, err.to_string())),
                 };
         
                 if let gemini::ResponseContent::Redirection { uri } = response.content() {
-                    url = match url.join(&uri) {
+                    url = match url.join(uri) {
                         Ok(url) => url,
                         Err(err) => return Ok(Tab::new_error(url, 0, err.to_string())),
                     };
@@ -98,6 +252,7 @@ impl Tab {
                     }
                     
                     redirections.push(url.clone());
+                    visited::visit(url.as_str());
                     
                     continue;
                 }
@@ -105,25 +260,41 @@ impl Tab {
                 match response.content() {
                     gemini::ResponseContent::InputExpected { prompt } => return  Err(ActionRequired::Input { prompt: prompt.into(), sensitive: response.status() == 11 }),
                     gemini::ResponseContent::Success { mimetype, body } => {
-                        if mimetype.len() == 0 {
+                        if mimetype.is_empty() {
                             return Ok(Tab::new_error(url, 0, io::Error::from(io::ErrorKind::InvalidData).to_string()));
                         }
 
-                        if mimetype.split(';').next().expect("unreachable").trim() == "text/gemini" {
-                            out.content = gemtext::GemText::new(&body);
-                            let title = body.lines()
-                                .filter(|l| l.starts_with("#"))
-                                .next()
-                                .map(|l| l.strip_prefix(['#', ' ']))
-                                .flatten();
-                            if let Some(title) = title {
-                                let title = title.trim();
-                                if title.len() > 0 {
-                                    out.title = title.into();
-                                }
+                        let (mimetype, _) = Self::mimetype(mimetype);
+                        let text = String::from_utf8_lossy(body);
+
+                        if let Some(feed) = xmlfeed::is_feed(mimetype).then(|| xmlfeed::parse(&text)).flatten() {
+                            out.content = gemtext::GemText::new(&xmlfeed::to_gemtext(&feed, &url));
+                            let title = feed.title.trim();
+                            if !title.is_empty() {
+                                out.title = title.into();
                             }
+                        } else if mimetype == "text/markdown" {
+                            out.content = gemtext::GemText::markdown(&text);
+                            let title = out.content.lines().find_map(|l| match l {
+                                gemtext::GemLine::Markdown(markdown::Block::Heading(_, title)) => Some(title.text.trim()),
+                                _ => None,
+                            });
+                            if let Some(title) = title.filter(|t| !t.is_empty()) {
+                                out.title = title.into();
+                            }
+                        } else if viewer::can_show(mimetype) {
+                            match viewer::Viewer::new(&url, mimetype, body.clone()) {
+                                Ok(viewer) => {
+                                    out.content = gemtext::GemText::raw(viewer.info.summary());
+                                    out.title = viewer.file_name();
+                                    out.viewer = Some(viewer);
+                                },
+                                Err(err) => out = Tab::new_error(url, 0, format!("Couldn't show the image: {err}")),
+                            }
+                        } else if mimetype.starts_with("text/") {
+                            out.content = gemtext::GemText::plain(&text);
                         } else {
-                            out.content = gemtext::GemText::raw(body);
+                            out.content = gemtext::GemText::raw(format!("Can't show {mimetype} files."));
                         }
                     },
                     gemini::ResponseContent::TemporaryFailure { error } => {
@@ -132,17 +303,100 @@ impl Tab {
                     gemini::ResponseContent::PermanentFailure { error } => {
                         out = Tab::new_error(url, response.status(), error);
                     },
-                    gemini::ResponseContent::ClientCertifiates { error } => todo!("client certificates"),
+                    gemini::ResponseContent::ClientCertifiates { error } => {
+                        // todo: client certificates
+                        out = Tab::new_error(url, response.status(), error);
+                    },
                     _ => unreachable!(),
                 }
     
                 break;
             }
 
+            out.raw = raw;
+            out.info = info;
             Ok(out)
         }));
     }
 
+    /// Read the rest of a gemtext page a chunk at a time, handing each line to the tab once it's complete.
+    fn read_gemtext(url: url::Url, mut stream: gemini::Stream, incoming: Arc<Mutex<Incoming>>) {
+        let mut parser = gemtext::Parser::default();
+        let mut body = Vec::new();
+        let mut chunk = vec![0; 16 * 1024];
+
+        loop {
+            let read = match io::Read::read(&mut stream, &mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    incoming.lock().expect("incoming lock poisoned").error = Some(err.to_string());
+                    return;
+                },
+            };
+            body.extend_from_slice(&chunk[..read]);
+            let lines = parser.push(&chunk[..read]);
+            incoming.lock().expect("incoming lock poisoned").lines.extend(lines);
+
+            // the tab was closed
+            if Arc::strong_count(&incoming) == 1 {
+                return;
+            }
+        }
+
+        let lines = parser.finish();
+        let diagnostics = lint::lint(&String::from_utf8_lossy(&body), &url);
+        let (raw, info) = stream.finish(body);
+
+        let mut incoming = incoming.lock().expect("incoming lock poisoned");
+        incoming.lines.extend(lines);
+        incoming.finished = Some((raw, info, diagnostics));
+    }
+
+    /// Show the lines of the page that arrived since the last call, returns whether there's more to come.
+    pub fn receive(&mut self) -> bool {
+        let Some(incoming) = self.incoming.clone() else { return false; };
+        let mut incoming = incoming.lock().expect("incoming lock poisoned");
+
+        let lines = std::mem::take(&mut incoming.lines);
+        // titled by its first heading
+        if self.title == Self::display_url(&self.url) {
+            let title = lines.iter().find_map(|(_, l)| match l {
+                gemtext::GemLine::Heading(_, title) => Some(title.trim()),
+                _ => None,
+            });
+            if let Some(title) = title.filter(|t| !t.is_empty()) {
+                self.title = title.into();
+            }
+        }
+        self.content.extend(lines);
+
+        if let Some((raw, info, diagnostics)) = incoming.finished.take() {
+            self.raw = Some(raw);
+            self.info = Some(info);
+            self.diagnostics = diagnostics;
+        } else if let Some(err) = incoming.error.take() {
+            log::warn!("couldn't read the rest of {}: {err}", self.url);
+        } else {
+            return true;
+        }
+
+        drop(incoming);
+        self.incoming = None;
+        false
+    }
+
+    /// The mimetype of a success response's `meta`, and the language given with it.
+    fn mimetype(meta: &str) -> (&str, Option<&str>) {
+        let (mimetype, params) = meta.split_once(';').unwrap_or((meta, ""));
+        let lang = params.split(';')
+            .filter_map(|p| p.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("lang"))
+            .map(|(_, lang)| lang.trim().trim_matches('"'));
+        (mimetype.trim(), lang)
+    }
+
     pub fn resolve(&mut self) -> Option<Result<Tab, ActionRequired>> {
         let Some(thread) = &self.request_thread else { return None; };
 
@@ -165,6 +419,18 @@ impl Tab {
         None
     }
 
+    /// Has never shown any content, the page it was created for should replace it rather than add to history.
+    pub fn is_blank(&self) -> bool {
+        self.content.id().is_none()
+    }
+
+    /// Make the downloads page again, to show progress since it was last made.
+    pub fn refresh_downloads(&mut self) {
+        if self.url.as_str() == downloads::PAGE_URL {
+            self.content = gemtext::GemText::new(&downloads::page());
+        }
+    }
+
     pub fn loading(&self) -> bool {
         self.request_thread.is_some()
     }
@@ -173,6 +439,11 @@ impl Tab {
         &self.url
     }
 
+    /// Host of the capsule this page is on, if it is on one.
+    pub fn capsule(&self) -> Option<&str> {
+        (self.url.scheme() == gemini::SCHEME).then(|| self.url.host_str()).flatten()
+    }
+
     pub fn title(&self) -> &str {
         &self.title
     }
@@ -181,6 +452,27 @@ impl Tab {
         &self.content
     }
 
+    /// The response the page was shown from, if it came from a capsule, or the one a source page shows.
+    pub fn raw(&self) -> Option<&gemini::RawResponse> {
+        self.raw.as_ref()
+    }
+
+    pub fn info(&self) -> Option<&gemini::Info> {
+        self.info.as_ref()
+    }
+
+    pub fn viewer(&self) -> Option<&viewer::Viewer> {
+        self.viewer.as_ref()
+    }
+
+    pub fn viewer_mut(&mut self) -> Option<&mut viewer::Viewer> {
+        self.viewer.as_mut()
+    }
+
+    pub fn diagnostics(&self) -> &[lint::Diagnostic] {
+        &self.diagnostics
+    }
+
     fn display_url(url: &url::Url) -> String {
         if url.scheme() == gemini::SCHEME {
             url.to_string()[url.scheme().len()+3..].into()
@@ -197,7 +489,12 @@ impl Default for Tab {
             display_url: String::new(),
             title: "New Tab".into(),
             content: gemtext::GemText::new(Tab::NEW_TEMPLATE),
+            diagnostics: Vec::new(),
+            viewer: None,
+            raw: None,
+            info: None,
             request_thread: None,
+            incoming: None,
         }
     }
 }
@@ -0,0 +1,223 @@
+//! Colour schemes for pages, built in or loaded from the user's theme files.
+
+use std::{fs, path::PathBuf, sync::{Mutex, MutexGuard}};
+
+use egui::Color32;
+
+/// Colours of a page, and whether the rest of the browser should be light or dark around it.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct Theme {
+    pub name: String,
+    pub dark: bool,
+    #[serde(with = "hex")]
+    pub background: Color32,
+    #[serde(with = "hex")]
+    pub text: Color32,
+    /// Heading levels one to three.
+    #[serde(with = "hex_array")]
+    pub headings: [Color32; 3],
+    #[serde(with = "hex")]
+    pub gemini_link: Color32,
+    /// Links to anything other than gemini, which are opened elsewhere.
+    #[serde(with = "hex")]
+    pub external_link: Color32,
+    #[serde(with = "hex")]
+    pub visited_link: Color32,
+    #[serde(with = "hex")]
+    pub quote: Color32,
+    #[serde(with = "hex")]
+    pub preformatted: Color32,
+}
+
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            name: "Dark".into(),
+            dark: true,
+            background: Color32::from_gray(10),
+            text: Color32::from_gray(180),
+            headings: [Color32::from_gray(240), Color32::from_gray(220), Color32::from_gray(200)],
+            gemini_link: Color32::from_rgb(90, 170, 255),
+            external_link: Color32::from_rgb(160, 140, 255),
+            visited_link: Color32::from_rgb(170, 120, 200),
+            quote: Color32::from_gray(140),
+            preformatted: Color32::from_gray(190),
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            name: "Light".into(),
+            dark: false,
+            background: Color32::from_gray(252),
+            text: Color32::from_gray(40),
+            headings: [Color32::from_gray(0), Color32::from_gray(15), Color32::from_gray(30)],
+            gemini_link: Color32::from_rgb(0, 90, 200),
+            external_link: Color32::from_rgb(90, 60, 200),
+            visited_link: Color32::from_rgb(120, 40, 150),
+            quote: Color32::from_gray(100),
+            preformatted: Color32::from_gray(50),
+        }
+    }
+
+    pub fn sepia() -> Theme {
+        Theme {
+            name: "Sepia".into(),
+            dark: false,
+            background: Color32::from_rgb(244, 236, 216),
+            text: Color32::from_rgb(91, 70, 54),
+            headings: [Color32::from_rgb(60, 40, 25), Color32::from_rgb(70, 50, 33), Color32::from_rgb(80, 60, 43)],
+            gemini_link: Color32::from_rgb(150, 75, 20),
+            external_link: Color32::from_rgb(120, 90, 30),
+            visited_link: Color32::from_rgb(130, 80, 90),
+            quote: Color32::from_rgb(130, 110, 90),
+            preformatted: Color32::from_rgb(75, 60, 50),
+        }
+    }
+
+    pub fn heading(&self, level: u8) -> Color32 {
+        self.headings[(level.clamp(1, 3) - 1) as usize]
+    }
+
+    /// Visuals for the browser around the page.
+    pub fn visuals(&self) -> egui::Visuals {
+        egui::Visuals {
+            hyperlink_color: self.gemini_link,
+            ..if self.dark { egui::Visuals::dark() } else { egui::Visuals::light() }
+        }
+    }
+}
+
+/// Which theme to use.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum ThemeChoice {
+    /// Light or dark to match the desktop.
+    System,
+    Named(String),
+}
+
+impl Default for ThemeChoice {
+    fn default() -> Self {
+        ThemeChoice::Named(Theme::dark().name)
+    }
+}
+
+static USER_THEMES: Mutex<Vec<Theme>> = Mutex::new(Vec::new());
+
+fn user_themes() -> MutexGuard<'static, Vec<Theme>> {
+    USER_THEMES.lock().expect("themes lock poisoned")
+}
+
+/// Where theme files are read from, a `.ron` file per theme.
+pub fn directory() -> Option<PathBuf> {
+    eframe::storage_dir("Vostok").map(|dir| dir.join("themes"))
+}
+
+/// Read the user's theme files, replacing any read before.
+pub fn load() {
+    let mut themes = Vec::new();
+
+    let entries = directory().and_then(|dir| fs::read_dir(dir).ok());
+    for path in entries.into_iter().flatten().flatten().map(|e| e.path()) {
+        if path.extension().is_some_and(|e| e == "ron") {
+            match fs::read_to_string(&path).map_err(|err| err.to_string()).and_then(|s| ron::from_str(&s).map_err(|err| err.to_string())) {
+                Ok(theme) => themes.push(theme),
+                Err(err) => log::warn!("couldn't load theme {}: {err}", path.display()),
+            }
+        }
+    }
+
+    themes.sort_by(|a: &Theme, b| a.name.cmp(&b.name));
+    *user_themes() = themes;
+}
+
+/// Names of every theme, built in first, each only once as user themes replace built in ones of the same name.
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = [Theme::dark(), Theme::light(), Theme::sepia()].into_iter().map(|t| t.name).collect();
+    for theme in user_themes().iter() {
+        if !names.contains(&theme.name) {
+            names.push(theme.name.clone());
+        }
+    }
+    names
+}
+
+/// The theme for `choice`, `system` is the desktop's preference if known.
+///
+/// Falls back to the dark theme if a named theme no longer exists.
+pub fn get(choice: &ThemeChoice, system: Option<eframe::Theme>) -> Theme {
+    let name = match choice {
+        ThemeChoice::System => return match system {
+            Some(eframe::Theme::Light) => Theme::light(),
+            _ => Theme::dark(),
+        },
+        ThemeChoice::Named(name) => name,
+    };
+
+    // user themes can replace built in ones of the same name
+    if let Some(theme) = user_themes().iter().find(|t| &t.name == name) {
+        return theme.clone();
+    }
+    [Theme::light(), Theme::sepia()].into_iter()
+        .find(|t| &t.name == name)
+        .unwrap_or_else(Theme::dark)
+}
+
+/// Colours are written as `#rrggbb` or `#rrggbbaa` in theme files.
+mod hex {
+    use serde::Deserialize;
+
+    pub fn serialize<S: serde::Serializer>(colour: &egui::Color32, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&colour.to_hex())
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<egui::Color32, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        egui::Color32::from_hex(&hex).map_err(|_| serde::de::Error::custom(format!("'{hex}' isn't a colour like #rrggbb")))
+    }
+}
+
+mod hex_array {
+    use serde::{Deserialize, Serialize};
+
+    pub fn serialize<S: serde::Serializer, const N: usize>(colours: &[egui::Color32; N], serializer: S) -> Result<S::Ok, S::Error> {
+        colours.iter().map(|c| c.to_hex()).collect::<Vec<_>>().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>, const N: usize>(deserializer: D) -> Result<[egui::Color32; N], D::Error> {
+        let hex = <Vec<String>>::deserialize(deserializer)?;
+        let count = hex.len();
+        hex.into_iter()
+            .map(|hex| egui::Color32::from_hex(&hex).map_err(|_| serde::de::Error::custom(format!("'{hex}' isn't a colour like #rrggbb"))))
+            .collect::<Result<Vec<_>, _>>()?
+            .try_into()
+            .map_err(|_| serde::de::Error::invalid_length(count, &format!("{N} colours").as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_files_round_trip() {
+        let source = r##"(
+            name: "Solarised",
+            dark: true,
+            background: "#002b36",
+            text: "#839496",
+            headings: ["#cb4b16", "#b58900", "#859900"],
+            gemini_link: "#268bd2",
+            external_link: "#6c71c4",
+            visited_link: "#d33682",
+            quote: "#93a1a1",
+            preformatted: "#eee8d5",
+        )"##;
+
+        let theme: Theme = ron::from_str(source).unwrap();
+        assert_eq!(theme.heading(2), Color32::from_rgb(0xb5, 0x89, 0x00));
+        assert_eq!(ron::from_str::<Theme>(&ron::to_string(&theme).unwrap()).unwrap(), theme);
+
+        assert!(ron::from_str::<Theme>(&source.replace("\"#859900\"", "")).is_err());
+    }
+}
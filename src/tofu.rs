@@ -0,0 +1,83 @@
+//! Certificates capsules presented the first time they were visited, trusted on first use.
+//!
+//! Nothing is rejected yet, whether a certificate matches is shown in the page info.
+
+use std::{collections::HashMap, sync::{Mutex, MutexGuard, OnceLock}};
+
+pub const STORAGE_KEY: &str = "known_hosts";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+struct Known {
+    /// SHA-256 of the certificate, see [`crate::gemini::Certificate::fingerprint`].
+    fingerprint: String,
+    /// Unix time it was first seen.
+    first_seen: i64,
+    /// Unix time the certificate stops being valid, after which a new one is expected.
+    expires: i64,
+}
+
+/// How a certificate compares to the one remembered for its host.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trust {
+    /// The host hasn't been visited before, this certificate is remembered from now on.
+    FirstUse,
+    /// The same certificate as first seen at this unix time.
+    Known(i64),
+    /// A different certificate to the one remembered, which had expired so this one replaces it.
+    Renewed,
+    /// A different certificate to the one first seen at this unix time, which is still valid.
+    Changed(i64),
+}
+
+/// Certificates by `host:port`.
+static KNOWN: OnceLock<Mutex<HashMap<String, Known>>> = OnceLock::new();
+
+fn known() -> MutexGuard<'static, HashMap<String, Known>> {
+    KNOWN.get_or_init(Default::default).lock().expect("known hosts lock poisoned")
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) {
+    if let Some(loaded) = storage.and_then(|storage| eframe::get_value(storage, STORAGE_KEY)) {
+        *known() = loaded;
+    }
+}
+
+pub fn save(storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, STORAGE_KEY, &*known());
+}
+
+/// Compare the certificate `host` presented to the one remembered for it, remembering it if there isn't one.
+pub fn check(host: &str, fingerprint: &str, expires: i64, now: i64) -> Trust {
+    let mut known = known();
+    let remembered = Known { fingerprint: fingerprint.into(), first_seen: now, expires };
+
+    let Some(previous) = known.get(host) else {
+        known.insert(host.into(), remembered);
+        return Trust::FirstUse;
+    };
+
+    if previous.fingerprint == fingerprint {
+        Trust::Known(previous.first_seen)
+    } else if previous.expires < now {
+        known.insert(host.into(), remembered);
+        Trust::Renewed
+    } else {
+        Trust::Changed(previous.first_seen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn certificates_are_trusted_on_first_use() {
+        let host = "tofu.example.org:1965";
+        assert_eq!(check(host, "aa", 200, 100), Trust::FirstUse);
+        assert_eq!(check(host, "aa", 200, 150), Trust::Known(100));
+        assert_eq!(check(host, "bb", 400, 150), Trust::Changed(100));
+        // once the first certificate expires a new one is expected
+        assert_eq!(check(host, "bb", 400, 300), Trust::Renewed);
+        assert_eq!(check(host, "bb", 400, 350), Trust::Known(300));
+    }
+}
@@ -0,0 +1,296 @@
+//! Image responses, shown on their own in a tab that can be zoomed and panned.
+
+use std::{io::{Cursor, Write}, path::PathBuf, sync::Arc, time::Duration};
+
+use image::{AnimationDecoder, ImageDecoder};
+
+use crate::{downloads, images};
+
+/// Furthest in or out the viewer zooms.
+const ZOOM_RANGE: std::ops::RangeInclusive<f32> = 0.05..=32.0;
+
+/// Most memory an animation's frames are decoded into, the frames after that are left out.
+const MAX_ANIMATION_BYTES: usize = 256 * 1024 * 1024;
+
+/// Types the viewer can show.
+const MIMETYPES: [&str; 6] = ["image/png", "image/jpeg", "image/gif", "image/webp", "image/svg+xml", "image/bmp"];
+
+pub fn can_show(mimetype: &str) -> bool {
+    MIMETYPES.contains(&mimetype)
+}
+
+/// What the server sent, for the page info.
+#[derive(Debug, Clone)]
+pub struct Info {
+    pub mimetype: String,
+    pub width: u32,
+    pub height: u32,
+    /// More than one for animations.
+    pub frames: usize,
+    /// Size of the response body in bytes.
+    pub size: usize,
+}
+
+impl Info {
+    /// One line summary, like `image/png, 800 × 600, 12.3 KiB`.
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}, {} \u{00D7} {}", self.mimetype, self.width, self.height);
+        if self.frames > 1 {
+            summary += &format!(", {} frames", self.frames);
+        }
+        summary + ", " + &format_size(self.size)
+    }
+}
+
+pub fn format_size(bytes: usize) -> String {
+    match bytes {
+        0..=1023 => format!("{bytes} B"),
+        1024..=1_048_575 => format!("{:.1} KiB", bytes as f32 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f32 / 1_048_576.0),
+    }
+}
+
+struct Frame {
+    /// Waiting to be uploaded, which has to happen on the UI thread.
+    image: Option<egui::ColorImage>,
+    texture: Option<egui::TextureHandle>,
+    delay: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Zoom {
+    /// As large as fits in the window, but no larger than the image itself.
+    Fit,
+    Scale(f32),
+}
+
+pub struct Viewer {
+    pub info: Info,
+    url: url::Url,
+    body: Arc<[u8]>,
+    frames: Vec<Frame>,
+    zoom: Zoom,
+    /// How far the image has been dragged from the centre.
+    offset: egui::Vec2,
+    /// Where the image was last saved to, or why it couldn't be.
+    saved: Option<Result<PathBuf, String>>,
+}
+
+impl Viewer {
+    /// Decode `body`, which the server said was a `mimetype` image.
+    pub fn new(url: &url::Url, mimetype: &str, body: Arc<[u8]>) -> Result<Viewer, String> {
+        let mut size = None;
+        let frames = match mimetype {
+            "image/svg+xml" => {
+                let (image, width, height) = render_svg(&body)?;
+                size = Some([width as usize, height as usize]);
+                vec![(image, Duration::ZERO)]
+            },
+            "image/gif" => animation(image::codecs::gif::GifDecoder::new(Cursor::new(&body)).map_err(|err| err.to_string())?)?,
+            "image/webp" => {
+                let decoder = image::codecs::webp::WebPDecoder::new(Cursor::new(&body)).map_err(|err| err.to_string())?;
+                if decoder.has_animation() {
+                    animation(decoder)?
+                } else {
+                    vec![(images::decode(&body)?, Duration::ZERO)]
+                }
+            },
+            _ => vec![(images::decode(&body)?, Duration::ZERO)],
+        };
+        let [width, height] = size.or(frames.first().map(|(f, _)| f.size)).ok_or("the image has no frames")?;
+
+        Ok(Viewer {
+            info: Info {
+                mimetype: mimetype.into(),
+                width: width as u32,
+                height: height as u32,
+                frames: frames.len(),
+                size: body.len(),
+            },
+            url: url.clone(),
+            body,
+            frames: frames.into_iter().map(|(image, delay)| Frame { image: Some(image), texture: None, delay }).collect(),
+            zoom: Zoom::Fit,
+            offset: egui::Vec2::ZERO,
+            saved: None,
+        })
+    }
+
+    /// Name to save the image as, from the last part of its url.
+    pub fn file_name(&self) -> String {
+        downloads::file_name(&self.url)
+    }
+
+    /// Write the image as it was sent to the downloads folder, without replacing anything already there.
+    fn save(&self) -> Result<PathBuf, String> {
+        let dir = downloads::directory().ok_or("there's no downloads folder")?;
+        let (path, mut file) = downloads::create_unique(&dir, &self.file_name()).map_err(|err| err.to_string())?;
+        file.write_all(&self.body).map_err(|err| err.to_string())?;
+        Ok(path)
+    }
+
+    /// Scale the image is shown at in `area`.
+    fn scale(&self, area: egui::Vec2) -> f32 {
+        match self.zoom {
+            Zoom::Fit => (area.x / self.info.width as f32).min(area.y / self.info.height as f32).min(1.0),
+            Zoom::Scale(scale) => scale,
+        }
+    }
+
+    fn zoom_by(&mut self, factor: f32, area: egui::Vec2, around: egui::Vec2) {
+        let old = self.scale(area);
+        let new = (old * factor).clamp(*ZOOM_RANGE.start(), *ZOOM_RANGE.end());
+        // the point under `around`, relative to the centre, stays where it is
+        self.offset = (self.offset - around) * (new / old) + around;
+        self.zoom = Zoom::Scale(new);
+    }
+
+    pub fn ui(&mut self, ui: &mut egui::Ui) {
+        for frame in &mut self.frames {
+            if let Some(image) = frame.image.take() {
+                frame.texture = Some(ui.ctx().load_texture(self.url.as_str(), image, egui::TextureOptions::LINEAR));
+            }
+        }
+
+        let area = ui.available_size() - egui::vec2(0.0, ui.spacing().interact_size.y + ui.spacing().item_spacing.y);
+        ui.horizontal(|ui| {
+            if ui.add(egui::SelectableLabel::new(self.zoom == Zoom::Fit, "Fit")).on_hover_text("Fit to window").clicked() {
+                self.zoom = Zoom::Fit;
+                self.offset = egui::Vec2::ZERO;
+            }
+            if ui.add(egui::SelectableLabel::new(self.zoom == Zoom::Scale(1.0), "1:1")).on_hover_text("Actual size").clicked() {
+                self.zoom = Zoom::Scale(1.0);
+                self.offset = egui::Vec2::ZERO;
+            }
+            if ui.button("\u{2212}").on_hover_text("Zoom out").clicked() {
+                self.zoom_by(1.0 / 1.25, area, egui::Vec2::ZERO);
+            }
+            if ui.button("+").on_hover_text("Zoom in").clicked() {
+                self.zoom_by(1.25, area, egui::Vec2::ZERO);
+            }
+            ui.label(format!("{:.0}%", self.scale(area) * 100.0));
+
+            if ui.button("Save").on_hover_text("Save to the downloads folder").clicked() {
+                self.saved = Some(self.save());
+            }
+            match &self.saved {
+                Some(Ok(path)) => { ui.weak(format!("Saved to {}", path.display())); },
+                Some(Err(err)) => { ui.colored_label(ui.visuals().error_fg_color, format!("Couldn't save: {err}")); },
+                None => { ui.weak(self.info.summary()); },
+            }
+        });
+
+        let (rect, response) = ui.allocate_exact_size(ui.available_size(), egui::Sense::drag());
+
+        if response.dragged() {
+            self.zoom = Zoom::Scale(self.scale(rect.size()));
+            self.offset += response.drag_delta();
+        }
+        if let Some(pointer) = response.hover_pos() {
+            let scroll = ui.input(|i| i.smooth_scroll_delta.y);
+            let factor = ui.input(|i| i.zoom_delta()) * (scroll / 200.0).exp();
+            if factor != 1.0 {
+                self.zoom_by(factor, rect.size(), pointer - rect.center());
+            }
+        }
+
+        let scale = self.scale(rect.size());
+        let size = egui::vec2(self.info.width as f32, self.info.height as f32) * scale;
+        let image_rect = egui::Rect::from_center_size(rect.center() + self.offset, size);
+
+        if let Some(texture) = self.current_frame(ui).and_then(|frame| frame.texture.as_ref()) {
+            let uv = egui::Rect::from_min_max(egui::Pos2::ZERO, egui::pos2(1.0, 1.0));
+            ui.painter_at(rect).image(texture.id(), image_rect, uv, egui::Color32::WHITE);
+        }
+    }
+
+    /// The frame to show now, asking for a repaint when the next one is due.
+    fn current_frame(&self, ui: &egui::Ui) -> Option<&Frame> {
+        let total: Duration = self.frames.iter().map(|f| f.delay).sum();
+        if self.frames.len() < 2 || total.is_zero() {
+            return self.frames.first();
+        }
+
+        let mut time = Duration::from_secs_f64(ui.input(|i| i.time) % total.as_secs_f64());
+        for frame in &self.frames {
+            if time < frame.delay {
+                ui.ctx().request_repaint_after(frame.delay - time);
+                return Some(frame);
+            }
+            time -= frame.delay;
+        }
+        self.frames.last()
+    }
+}
+
+/// The frames of an animation and how long each is shown for, as many as fit in [`MAX_ANIMATION_BYTES`].
+fn animation<'a>(decoder: impl AnimationDecoder<'a> + ImageDecoder) -> Result<Vec<(egui::ColorImage, Duration)>, String> {
+    // every frame is decoded at full size before it's scaled down
+    let (width, height) = decoder.dimensions();
+    if width as usize * height as usize * 4 > MAX_ANIMATION_BYTES {
+        return Err("the image is too large".into());
+    }
+
+    let mut frames = Vec::new();
+    let mut decoded = 0;
+    for frame in decoder.into_frames() {
+        let frame = frame.map_err(|err| err.to_string())?;
+        let (numerator, denominator) = frame.delay().numer_denom_ms();
+        // browsers treat very short delays as 100ms, lots of gifs rely on it
+        let delay = match numerator / denominator.max(1) {
+            0..=10 => Duration::from_millis(100),
+            ms => Duration::from_millis(ms as u64),
+        };
+        let image = images::colour_image(image::DynamicImage::ImageRgba8(frame.into_buffer()));
+
+        decoded += image.pixels.len() * 4;
+        if decoded > MAX_ANIMATION_BYTES {
+            break;
+        }
+        frames.push((image, delay));
+    }
+    Ok(frames)
+}
+
+/// Render an svg at twice its size, so it stays sharp when zoomed in a little, returning the size it should be shown at.
+fn render_svg(body: &[u8]) -> Result<(egui::ColorImage, u32, u32), String> {
+    let tree = resvg::usvg::Tree::from_data(body, &resvg::usvg::Options::default()).map_err(|err| err.to_string())?;
+    let size = tree.size();
+    let scale = (2.0f32).min(images::MAX_SIZE as f32 / size.width().max(size.height()));
+    let (width, height) = ((size.width() * scale).ceil() as u32, (size.height() * scale).ceil() as u32);
+
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height).ok_or("the image has no size")?;
+    resvg::render(&tree, resvg::tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+    let image = egui::ColorImage::from_rgba_premultiplied([width as usize, height as usize], pixmap.data());
+    Ok((image, size.width().ceil() as u32, size.height().ceil() as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn images_are_decoded() {
+        let svg = br#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20"><rect width="40" height="20" fill="red"/></svg>"#;
+        let url = url::Url::parse("gemini://example.org/pictures/a%20box.svg").unwrap();
+        let viewer = Viewer::new(&url, "image/svg+xml", svg.as_slice().into()).unwrap();
+        // svgs are rendered larger than they are, but shown at their own size
+        assert_eq!((viewer.info.width, viewer.info.height), (40, 20));
+        assert_eq!(viewer.frames[0].image.as_ref().unwrap().size, [80, 40]);
+        assert_eq!(viewer.file_name(), "a box.svg");
+
+        let mut gif = Vec::new();
+        {
+            let mut encoder = image::codecs::gif::GifEncoder::new(&mut gif);
+            let frames = [image::Rgba([255, 0, 0, 255]), image::Rgba([0, 0, 255, 255])].map(|colour| {
+                image::Frame::from_parts(image::RgbaImage::from_pixel(4, 3, colour), 0, 0, image::Delay::from_numer_denom_ms(50, 1))
+            });
+            encoder.encode_frames(frames).unwrap();
+        }
+        let viewer = Viewer::new(&url, "image/gif", gif.into()).unwrap();
+        assert_eq!((viewer.info.width, viewer.info.height, viewer.info.frames), (4, 3, 2));
+        assert_eq!(viewer.frames[0].delay, Duration::from_millis(50));
+
+        assert!(Viewer::new(&url, "image/png", b"not a png".as_slice().into()).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use std::{collections::HashMap, sync::{Mutex, MutexGuard, OnceLock}, time::{SystemTime, UNIX_EPOCH}};
+
+pub const STORAGE_KEY: &str = "visited";
+
+/// Most pages remembered, the ones visited longest ago are forgotten first.
+const MAX_VISITED: usize = 10_000;
+
+/// Absolute urls of pages opened and when they were last opened, for colouring links to them.
+static VISITED: OnceLock<Mutex<HashMap<String, u64>>> = OnceLock::new();
+
+fn visited() -> MutexGuard<'static, HashMap<String, u64>> {
+    VISITED.get_or_init(Default::default).lock().expect("visited lock poisoned")
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) {
+    if let Some(loaded) = storage.and_then(|storage| eframe::get_value(storage, STORAGE_KEY)) {
+        *visited() = loaded;
+    }
+}
+
+pub fn save(storage: &mut dyn eframe::Storage) {
+    eframe::set_value(storage, STORAGE_KEY, &*visited());
+}
+
+pub fn visit(url: &str) {
+    let mut visited = visited();
+    visited.insert(url.into(), now());
+    // trimmed in batches so it isn't sorted on every visit
+    if visited.len() > MAX_VISITED + MAX_VISITED / 10 {
+        forget_oldest(&mut visited, MAX_VISITED);
+    }
+}
+
+pub fn is_visited(url: &str) -> bool {
+    visited().contains_key(url)
+}
+
+/// Keep only the `max` most recent visits.
+fn forget_oldest(visited: &mut HashMap<String, u64>, max: usize) {
+    let mut times: Vec<u64> = visited.values().copied().collect();
+    times.sort_unstable_by(|a, b| b.cmp(a));
+    let Some(&oldest_kept) = times.get(max.saturating_sub(1)) else {
+        return;
+    };
+    visited.retain(|_, time| *time >= oldest_kept);
+
+    // visits at the same time as the oldest kept one can still be too many
+    let extra = visited.len().saturating_sub(max);
+    let same: Vec<String> = visited.iter().filter(|(_, t)| **t == oldest_kept).map(|(url, _)| url.clone()).take(extra).collect();
+    for url in same {
+        visited.remove(&url);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oldest_visits_are_forgotten() {
+        let mut visited: HashMap<String, u64> = (0..10).map(|i| (format!("gemini://example.org/{i}"), i / 2)).collect();
+        forget_oldest(&mut visited, 5);
+        assert_eq!(visited.len(), 5);
+        assert!(visited.values().all(|t| *t >= 2));
+        assert!(visited.contains_key("gemini://example.org/9"));
+    }
+}
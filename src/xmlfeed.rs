@@ -0,0 +1,263 @@
+//! Atom and RSS feeds, shown as gemtext.
+
+use std::fmt::Write;
+
+/// Longest summary shown under an entry, in characters.
+const SUMMARY_LENGTH: usize = 280;
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+#[derive(Debug, Default)]
+pub struct Feed {
+    pub title: String,
+    pub link: Option<String>,
+    pub description: String,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, Default)]
+pub struct Entry {
+    pub title: String,
+    pub link: Option<String>,
+    /// `YYYY-MM-DD`
+    pub date: Option<String>,
+    pub summary: String,
+}
+
+/// Whether responses of this mimetype (without parameters) should be parsed with [`parse`].
+pub fn is_feed(mimetype: &str) -> bool {
+    matches!(mimetype, "application/atom+xml" | "application/rss+xml")
+}
+
+/// Parse an Atom, RSS 2.0 or RSS 1.0 document.
+pub fn parse(xml: &str) -> Option<Feed> {
+    let doc = roxmltree::Document::parse(xml).ok()?;
+    let root = doc.root_element();
+
+    match root.tag_name().name() {
+        "feed" => Some(parse_atom(root)),
+        "rss" | "RDF" => Some(parse_rss(root)),
+        _ => None,
+    }
+}
+
+fn child<'a, 'i>(node: roxmltree::Node<'a, 'i>, name: &str) -> Option<roxmltree::Node<'a, 'i>> {
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+/// All the text inside `node`, including CDATA and nested elements.
+fn text(node: Option<roxmltree::Node>) -> String {
+    let Some(node) = node else { return String::new(); };
+    node.descendants()
+        .filter(|n| n.is_text())
+        .filter_map(|n| n.text())
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+fn parse_atom(feed: roxmltree::Node) -> Feed {
+    // links without a rel are alternate links, i.e. the page itself
+    let alternate = |node: roxmltree::Node| node.children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "link")
+        .find(|n| n.attribute("rel").is_none_or(|rel| rel == "alternate"))
+        .and_then(|n| n.attribute("href"))
+        .map(str::to_owned);
+
+    Feed {
+        title: text(child(feed, "title")),
+        link: alternate(feed),
+        description: text(child(feed, "subtitle")),
+        entries: feed.children()
+            .filter(|n| n.is_element() && n.tag_name().name() == "entry")
+            .map(|entry| Entry {
+                title: text(child(entry, "title")),
+                link: alternate(entry),
+                date: child(entry, "published").or_else(|| child(entry, "updated"))
+                    .and_then(|n| iso_date(&text(Some(n)))),
+                summary: text(child(entry, "summary").or_else(|| child(entry, "content"))),
+            })
+            .collect(),
+    }
+}
+
+fn parse_rss(root: roxmltree::Node) -> Feed {
+    let channel = root.descendants().find(|n| n.is_element() && n.tag_name().name() == "channel");
+
+    // RSS 1.0 puts items beside the channel rather than in it
+    let items = root.descendants().filter(|n| n.is_element() && n.tag_name().name() == "item");
+
+    Feed {
+        title: text(channel.and_then(|c| child(c, "title"))),
+        link: channel.and_then(|c| child(c, "link")).map(|n| text(Some(n))).filter(|l| !l.is_empty()),
+        description: text(channel.and_then(|c| child(c, "description"))),
+        entries: items
+            .map(|item| Entry {
+                title: text(child(item, "title")),
+                link: child(item, "link").map(|n| text(Some(n)))
+                    .filter(|l| !l.is_empty())
+                    .or_else(|| item.attribute((RDF_NS, "about")).map(str::to_owned)),
+                date: child(item, "pubDate").and_then(|n| rfc822_date(&text(Some(n))))
+                    .or_else(|| child(item, "date").and_then(|n| iso_date(&text(Some(n))))),
+                summary: text(child(item, "description")),
+            })
+            .collect(),
+    }
+}
+
+/// The date part of an RFC 3339 timestamp, e.g. `2024-05-21T10:00:00Z`.
+fn iso_date(s: &str) -> Option<String> {
+    let date = s.get(..10)?;
+    let valid = date.bytes().enumerate().all(|(i, b)| match i {
+        4 | 7 => b == b'-',
+        _ => b.is_ascii_digit(),
+    });
+    valid.then(|| date.to_owned())
+}
+
+/// The date of an RFC 822 timestamp, e.g. `Tue, 21 May 2024 10:00:00 GMT`.
+fn rfc822_date(s: &str) -> Option<String> {
+    let s = s.split_once(',').map_or(s, |(_, rest)| rest);
+    let mut parts = s.split_whitespace();
+
+    let day: u8 = parts.next()?.parse().ok()?;
+    let month = parts.next()?.get(..3)?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| *m == month)? + 1;
+    let year: u16 = parts.next()?.parse().ok()?;
+    // two digit years are from before anyone published feeds over gemini
+    let year = if year < 100 { year + 1900 } else { year };
+
+    Some(format!("{year:04}-{month:02}-{day:02}"))
+}
+
+/// Plain text of a possibly HTML summary, on one line and cut short.
+fn plain(html: &str) -> String {
+    let mut text = String::new();
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                text.push(' ');
+            },
+            c if !in_tag => text.push(c),
+            _ => {},
+        }
+    }
+
+    let text = text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if text.chars().count() > SUMMARY_LENGTH {
+        text.chars().take(SUMMARY_LENGTH).collect::<String>().trim_end().to_owned() + "\u{2026}"
+    } else {
+        text
+    }
+}
+
+/// Keep text from being read as any gemtext line other than a text line.
+fn text_line(text: &str) -> String {
+    if text.starts_with(['#', '`', '>']) || text.starts_with("=>") || text.starts_with("* ") {
+        format!("\u{200b}{text}")
+    } else {
+        text.into()
+    }
+}
+
+/// Render a feed as gemtext, entries become gemfeed style links so the page can be subscribed to.
+pub fn to_gemtext(feed: &Feed, url: &url::Url) -> String {
+    let join = |link: &str| url.join(link).map(|u| u.to_string()).unwrap_or_else(|_| link.to_owned());
+
+    let title = plain(&feed.title);
+    let mut page = format!("# {}\n\n", if title.is_empty() { "Untitled feed" } else { &title });
+
+    let description = plain(&feed.description);
+    if !description.is_empty() {
+        page += &text_line(&description);
+        page += "\n";
+    }
+    if let Some(link) = &feed.link {
+        let _ = writeln!(page, "=> {} Home page", join(link));
+    }
+
+    for entry in &feed.entries {
+        page += "\n";
+
+        let title = plain(&entry.title);
+        let title = if title.is_empty() { "Untitled" } else { &title };
+        let date = entry.date.as_deref().map(|d| format!("{d} ")).unwrap_or_default();
+
+        match &entry.link {
+            Some(link) => {
+                let _ = writeln!(page, "=> {} {date}{title}", join(link));
+            },
+            None => {
+                let _ = writeln!(page, "### {date}{title}");
+            },
+        }
+
+        let summary = plain(&entry.summary);
+        if !summary.is_empty() {
+            page += &text_line(&summary);
+            page += "\n";
+        }
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{feeds, gemtext};
+
+    #[test]
+    fn feeds_can_be_subscribed_to() {
+        let url = url::Url::parse("gemini://example.org/feed.xml").unwrap();
+
+        let atom = r#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <title>Example Gemlog</title>
+                <link href="gemini://example.org/"/>
+                <entry>
+                    <title>Hello &amp; welcome</title>
+                    <link rel="alternate" href="/posts/hello.gmi"/>
+                    <updated>2024-05-21T10:00:00Z</updated>
+                    <summary type="html">&lt;p&gt;First post&lt;/p&gt;</summary>
+                </entry>
+            </feed>"#;
+        let rss = r#"<?xml version="1.0"?>
+            <rss version="2.0"><channel>
+                <title>Example Gemlog</title>
+                <item>
+                    <title>Hello &amp; welcome</title>
+                    <link>gemini://example.org/posts/hello.gmi</link>
+                    <pubDate>Tue, 21 May 2024 10:00:00 GMT</pubDate>
+                    <description><![CDATA[<p>First post</p>]]></description>
+                </item>
+            </channel></rss>"#;
+
+        for xml in [atom, rss] {
+            let feed = parse(xml).expect("valid feed");
+            assert_eq!(feed.title, "Example Gemlog");
+
+            let page = gemtext::GemText::new(&to_gemtext(&feed, &url));
+            let entries = feeds::parse_gemfeed(&url, &page);
+
+            assert_eq!(entries.len(), 1);
+            assert_eq!(entries[0].url, "gemini://example.org/posts/hello.gmi");
+            assert_eq!(entries[0].date, "2024-05-21");
+            assert_eq!(entries[0].title, "Hello & welcome");
+            assert!(page.lines().any(|l| l.text() == "First post"));
+        }
+    }
+}